@@ -3,9 +3,11 @@ use std::time::Instant;
 
 use eframe::egui;
 use eframe::egui::Widget;
+use eyre::Result;
 use memeroute::dsn::pcb_to_session::PcbToSession;
 use memeroute::model::pcb::Pcb;
-use memeroute::route::router::{apply_route_result, Router};
+use memeroute::name::Id;
+use memeroute::route::router::{apply_route_result, reroute_net_with_radius, Router};
 use serde::{Deserialize, Serialize};
 
 use crate::pcb::pcb_view::PcbView;
@@ -15,11 +17,12 @@ use crate::pcb::pcb_view::PcbView;
 #[serde(default)]
 struct State {
     filename: String,
+    color_by_net: bool,
 }
 
 impl Default for State {
     fn default() -> Self {
-        Self { filename: "data/left.dsn".to_string() }
+        Self { filename: "data/left.dsn".to_string(), color_by_net: false }
     }
 }
 
@@ -30,6 +33,9 @@ pub struct MemerouteGui {
     pcb: Pcb,
     pcb_view: PcbView,
     data_path: PathBuf,
+    net: Option<Id>, // Net selected for editing its width.
+    net_radius: f64,
+    status: String, // Result of the last action, shown in the side panel.
 }
 
 impl MemerouteGui {
@@ -39,8 +45,26 @@ impl MemerouteGui {
         } else {
             State::default()
         };
-        let pcb_view = PcbView::new(pcb.clone(), pcb.bounds());
-        Self { s, pcb, pcb_view, data_path: data_path.as_ref().into() }
+        let bounds = if pcb.boundaries().is_empty() { pcb.content_bounds() } else { pcb.bounds() };
+        let mut pcb_view = PcbView::new(pcb.clone(), bounds);
+        pcb_view.set_color_by_net(s.color_by_net);
+        Self {
+            s,
+            pcb,
+            pcb_view,
+            data_path: data_path.as_ref().into(),
+            net: None,
+            net_radius: 0.1,
+            status: String::new(),
+        }
+    }
+
+    // Changes the radius of the given net and reroutes just that net. On
+    // failure the previous routing is kept.
+    fn set_net_radius(&mut self, net_id: Id, radius: f64) -> Result<()> {
+        reroute_net_with_radius(&mut self.pcb, net_id, radius)?;
+        self.pcb_view.set_pcb(self.pcb.clone());
+        Ok(())
     }
 }
 
@@ -63,6 +87,10 @@ impl eframe::App for MemerouteGui {
         egui::SidePanel::left("side_panel").show(ctx, |ui| {
             ui.heading("Side Panel");
 
+            if ui.checkbox(&mut self.s.color_by_net, "Color by net").changed() {
+                self.pcb_view.set_color_by_net(self.s.color_by_net);
+            }
+
             if ui.button("Route").clicked() {
                 let router = Router::new(self.pcb.clone());
                 let start = Instant::now();
@@ -84,6 +112,33 @@ impl eframe::App for MemerouteGui {
                 // Update pcb view.
                 self.pcb_view.set_pcb(self.pcb.clone());
             }
+
+            ui.separator();
+            let mut nets: Vec<_> = self
+                .pcb
+                .nets()
+                .filter(|v| !v.is_fixed())
+                .map(|v| (self.pcb.to_name(v.id), v.id))
+                .collect();
+            nets.sort();
+            let selected = self.net.map_or_else(String::new, |v| self.pcb.to_name(v));
+            egui::ComboBox::from_label("Net").selected_text(selected).show_ui(ui, |ui| {
+                for (name, id) in nets {
+                    ui.selectable_value(&mut self.net, Some(id), name);
+                }
+            });
+            ui.add(egui::DragValue::new(&mut self.net_radius).speed(0.01).prefix("Radius: "));
+            if ui.button("Reroute net").clicked() {
+                if let Some(net_id) = self.net {
+                    self.status = match self.set_net_radius(net_id, self.net_radius) {
+                        Ok(()) => format!("Rerouted net {}", self.pcb.to_name(net_id)),
+                        Err(e) => format!("Failed to reroute net: {e}"),
+                    };
+                }
+            }
+            if !self.status.is_empty() {
+                ui.label(&self.status);
+            }
         });
 
         egui::CentralPanel::default().show(ctx, |ui| {
@@ -44,9 +44,7 @@ use std::path::{Path, PathBuf};
 
 use clap::Parser;
 use eyre::Result;
-use memedsn::lexer::Lexer;
-use memedsn::parser;
-use memeroute::dsn::design_to_pcb::DesignToPcb;
+use memeroute::dsn::load_dsn;
 use memeroute::model::pcb::Pcb;
 
 use crate::gui::MemerouteGui;
@@ -67,11 +65,7 @@ struct Args {
 
 fn load_pcb<P: AsRef<Path>>(path: P) -> Result<Pcb> {
     let data = read_to_string(path)?;
-    let lexer = Lexer::new(&data)?;
-    let parser = parser::Parser::new(&lexer.lex()?);
-    let pcb = parser.parse()?;
-    let pcb = DesignToPcb::new(pcb).convert()?;
-    Ok(pcb)
+    Ok(load_dsn(&data)?)
 }
 
 pub fn run() -> Result<()> {
@@ -1,6 +1,6 @@
 use std::sync::LazyLock;
 
-use eframe::egui::epaint::{Mesh, TessellationOptions, Tessellator};
+use eframe::egui::epaint::{Hsva, Mesh, TessellationOptions, Tessellator};
 use eframe::egui::{epaint, Color32, Context, PointerButton, Response, Sense, Ui, Widget};
 use eframe::epaint::Fonts;
 use memegeom::primitive::point::Pt;
@@ -9,10 +9,11 @@ use memegeom::primitive::shape::Shape;
 use memegeom::primitive::{path, pt, ShapeOps};
 use memegeom::tf::Tf;
 use memeroute::model::pcb::{
-    Component, Keepout, LayerId, LayerSet, LayerShape, Padstack, Pcb, Pin,
+    Component, Keepout, KeepoutType, LayerId, LayerSet, LayerShape, Padstack, Pcb, Pin,
 };
+use memeroute::name::Id;
 
-use crate::pcb::primitives::{fill_circle, fill_polygon, fill_rt, stroke_path};
+use crate::pcb::primitives::{fill_circle, fill_polygon, fill_rt, stroke_path, stroke_polygon};
 use crate::pcb::{to_pos2, to_pt, to_rt};
 
 // Index 0 is front, index 1 is back.
@@ -21,6 +22,12 @@ use crate::pcb::{to_pos2, to_pt, to_rt};
 static KEEPOUT: LazyLock<Color32> =
     LazyLock::new(|| Color32::from_rgba_unmultiplied(155, 27, 0, 180));
 
+static VIA_KEEPOUT: LazyLock<Color32> =
+    LazyLock::new(|| Color32::from_rgba_unmultiplied(214, 120, 0, 180));
+
+static WIRE_KEEPOUT: LazyLock<Color32> =
+    LazyLock::new(|| Color32::from_rgba_unmultiplied(196, 0, 84, 180));
+
 static OUTLINE: LazyLock<[Color32; 2]> = LazyLock::new(|| {
     [
         Color32::from_rgba_unmultiplied(89, 113, 193, 180),
@@ -51,6 +58,18 @@ static VIA: LazyLock<Color32> =
 static DEBUG: LazyLock<Color32> =
     LazyLock::new(|| Color32::from_rgba_unmultiplied(123, 0, 255, 180));
 
+static UNSUPPORTED: LazyLock<Color32> =
+    LazyLock::new(|| Color32::from_rgba_unmultiplied(255, 0, 255, 220));
+
+// Returns a color for the given net. The same net always gets the same color.
+// Hues are spread using the golden ratio so nearby ids get distinct colors.
+#[must_use]
+pub fn net_color(id: Id) -> Color32 {
+    const GOLDEN: f64 = 0.618_033_988_749_895;
+    let hue = (id as f64 * GOLDEN).fract() as f32;
+    Hsva::new(hue, 0.85, 0.8, 0.7).into()
+}
+
 #[must_use]
 #[derive(Debug, Clone)]
 pub struct PcbView {
@@ -60,6 +79,8 @@ pub struct PcbView {
     offset: Pt,
     zoom: f64,
     dirty: bool,
+    deterministic: bool, // Use our own tessellation instead of egui's.
+    color_by_net: bool,  // Color wires and vias by net instead of by layer.
     mesh: Mesh,
 }
 
@@ -95,6 +116,8 @@ impl PcbView {
             pcb,
             local_area,
             dirty: true,
+            deterministic: false,
+            color_by_net: false,
             offset: Pt::zero(),
             zoom: 1.0,
             screen_area: Rt::default(),
@@ -129,12 +152,24 @@ impl PcbView {
                 let r = if s.r() == 0.0 { 0.1 } else { s.r() };
                 shapes.extend(stroke_path(tf, s.pts(), r, col));
             }
-            _ => todo!(),
+            _ => {
+                // Draw shapes we can't tessellate as a crossed out bounding box, so
+                // missing geometry is noticeable.
+                let pts = v.shape.bounds().pts();
+                shapes.extend(stroke_polygon(tf, &pts, 0.05, *UNSUPPORTED));
+                shapes.extend(stroke_path(tf, &[pts[0], pts[2]], 0.05, *UNSUPPORTED));
+                shapes.extend(stroke_path(tf, &[pts[1], pts[3]], 0.05, *UNSUPPORTED));
+            }
         }
         shapes
     }
 
-    fn draw_keepout(tf: &Tf, v: &Keepout, col: Color32) -> Vec<epaint::Shape> {
+    fn draw_keepout(tf: &Tf, v: &Keepout) -> Vec<epaint::Shape> {
+        let col = match v.kind {
+            KeepoutType::Keepout => *KEEPOUT,
+            KeepoutType::ViaKeepout => *VIA_KEEPOUT,
+            KeepoutType::WireKeepout => *WIRE_KEEPOUT,
+        };
         Self::draw_shape(tf, &v.shape, col)
     }
 
@@ -159,7 +194,7 @@ impl PcbView {
             shapes.extend(Self::draw_shape(&tf, outline, OUTLINE[idx]));
         }
         for keepout in &v.keepouts {
-            shapes.extend(Self::draw_keepout(&tf, keepout, *KEEPOUT));
+            shapes.extend(Self::draw_keepout(&tf, keepout));
         }
         for pin in v.pins() {
             let idx = pin.padstack.layers().first().unwrap();
@@ -168,12 +203,48 @@ impl PcbView {
         shapes
     }
 
-    fn tessellate(tess: &mut Tessellator, mesh: &mut Mesh, shapes: Vec<epaint::Shape>) {
+    fn tessellate(&self, tess: &mut Tessellator, mesh: &mut Mesh, shapes: Vec<epaint::Shape>) {
         for s in shapes {
-            tess.tessellate_shape(s, mesh);
+            if self.deterministic {
+                Self::tessellate_deterministic(mesh, s);
+            } else {
+                tess.tessellate_shape(s, mesh);
+            }
         }
     }
 
+    // Tessellation which doesn't depend on egui's tessellator, so output is stable
+    // e.g. for snapshot tests. Only handles the shapes produced by draw_shape.
+    fn tessellate_deterministic(mesh: &mut Mesh, s: epaint::Shape) {
+        match s {
+            epaint::Shape::Mesh(m) => mesh.append(m),
+            epaint::Shape::Path(p) if p.closed && p.points.len() >= 3 => {
+                // Paths are convex (circles), so use a triangle fan.
+                let idx = mesh.vertices.len() as u32;
+                for &pos in &p.points {
+                    mesh.colored_vertex(pos, p.fill);
+                }
+                for i in 1..(p.points.len() as u32 - 1) {
+                    mesh.add_triangle(idx, idx + i, idx + i + 1);
+                }
+            }
+            s => debug_assert!(
+                matches!(s, epaint::Shape::Noop),
+                "deterministic tessellation doesn't support {s:?}"
+            ),
+        }
+    }
+
+    pub fn set_deterministic_tessellation(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+        self.mesh.clear(); // Regenerate mesh.
+    }
+
+    pub fn set_color_by_net(&mut self, color_by_net: bool) {
+        self.color_by_net = color_by_net;
+        self.mesh.clear(); // Regenerate mesh.
+    }
+
     fn render(&mut self, ctx: &Context) -> Mesh {
         if self.mesh.is_empty() {
             let mut mesh = Mesh::default();
@@ -186,25 +257,30 @@ impl PcbView {
             );
             for boundary in self.pcb.boundaries() {
                 let shapes = Self::draw_shape(&tf, boundary, *BOUNDARY);
-                Self::tessellate(&mut tess, &mut mesh, shapes);
+                self.tessellate(&mut tess, &mut mesh, shapes);
             }
             for keepout in self.pcb.keepouts() {
-                let shapes = Self::draw_keepout(&tf, keepout, *KEEPOUT);
-                Self::tessellate(&mut tess, &mut mesh, shapes);
+                let shapes = Self::draw_keepout(&tf, keepout);
+                self.tessellate(&mut tess, &mut mesh, shapes);
             }
             for component in self.pcb.components() {
                 let shapes = Self::draw_component(&tf, component);
-                Self::tessellate(&mut tess, &mut mesh, shapes);
+                self.tessellate(&mut tess, &mut mesh, shapes);
             }
             for wire in self.pcb.wires() {
                 // TODO!!: Fix up layerset to color mapping.
-                let col = WIRE[Self::layer_id_to_color_idx(wire.shape.layers.id().unwrap())];
+                let col = if self.color_by_net {
+                    net_color(wire.net_id)
+                } else {
+                    WIRE[Self::layer_id_to_color_idx(wire.shape.layers.id().unwrap())]
+                };
                 let shapes = Self::draw_shape(&tf, &wire.shape, col);
-                Self::tessellate(&mut tess, &mut mesh, shapes);
+                self.tessellate(&mut tess, &mut mesh, shapes);
             }
             for via in self.pcb.vias() {
-                let shapes = Self::draw_padstack(&via.tf(), &via.padstack, *VIA);
-                Self::tessellate(&mut tess, &mut mesh, shapes);
+                let col = if self.color_by_net { net_color(via.net_id) } else { *VIA };
+                let shapes = Self::draw_padstack(&via.tf(), &via.padstack, col);
+                self.tessellate(&mut tess, &mut mesh, shapes);
             }
             for rt in self.pcb.debug_rts() {
                 let mut pts = rt.pts().to_vec();
@@ -212,7 +288,7 @@ impl PcbView {
                 let shape = path(&pts, 0.05).shape();
                 let shapes =
                     Self::draw_shape(&tf, &LayerShape { shape, layers: LayerSet::empty() }, *DEBUG);
-                Self::tessellate(&mut tess, &mut mesh, shapes);
+                self.tessellate(&mut tess, &mut mesh, shapes);
             }
             self.mesh = mesh;
         }
@@ -232,3 +308,67 @@ impl PcbView {
         mesh
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use memegeom::primitive::{circ, rt, seg};
+
+    use super::*;
+
+    fn layer_shape(shape: Shape) -> LayerShape {
+        LayerShape { layers: LayerSet::one(0), shape }
+    }
+
+    fn mesh(shapes: Vec<epaint::Shape>) -> Mesh {
+        let mut mesh = Mesh::default();
+        for s in shapes {
+            PcbView::tessellate_deterministic(&mut mesh, s);
+        }
+        mesh
+    }
+
+    #[test]
+    fn net_color_stable_and_distinct() {
+        assert_eq!(net_color(3), net_color(3));
+        let colors: Vec<Color32> = (0..8).map(net_color).collect();
+        for (i, a) in colors.iter().enumerate() {
+            for b in &colors[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_shape_draws_marker() {
+        let shape = layer_shape(seg(pt(0.0, 0.0), pt(1.0, 1.0)).shape());
+        let mesh = mesh(PcbView::draw_shape(&Tf::new(), &shape, Color32::BLACK));
+        assert!(!mesh.indices.is_empty());
+        assert!(mesh.vertices.iter().all(|v| v.color == *UNSUPPORTED));
+    }
+
+    #[test]
+    fn keepout_kinds_have_distinct_colors() {
+        let kinds = [KeepoutType::Keepout, KeepoutType::ViaKeepout, KeepoutType::WireKeepout];
+        let colors: Vec<Color32> = kinds
+            .into_iter()
+            .map(|kind| {
+                let shape = layer_shape(rt(0.0, 0.0, 1.0, 1.0).shape());
+                let k = Keepout { kind, shape, windows: Vec::new() };
+                mesh(PcbView::draw_keepout(&Tf::new(), &k)).vertices[0].color
+            })
+            .collect();
+        assert_ne!(colors[0], colors[1]);
+        assert_ne!(colors[0], colors[2]);
+        assert_ne!(colors[1], colors[2]);
+    }
+
+    #[test]
+    fn deterministic_triangle_count() {
+        let rect = layer_shape(rt(0.0, 0.0, 2.0, 1.0).shape());
+        assert_eq!(mesh(PcbView::draw_shape(&Tf::new(), &rect, Color32::BLACK)).indices.len(), 6);
+        // Circles are a fan over 2 * 16 points.
+        let circle = layer_shape(circ(pt(0.0, 0.0), 1.0).shape());
+        let mesh = mesh(PcbView::draw_shape(&Tf::new(), &circle, Color32::BLACK));
+        assert_eq!(mesh.indices.len(), 3 * 30);
+    }
+}
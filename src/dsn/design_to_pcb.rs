@@ -1,46 +1,167 @@
+use std::cell::RefCell;
+use std::f64::consts::PI;
+
 use ahash::HashMap;
 use eyre::{eyre, Result};
 use itertools::Itertools;
 use memedsn::types::{
     DsnCircuit, DsnClass, DsnClearance, DsnClearanceType, DsnComponent, DsnDimensionUnit, DsnImage,
-    DsnKeepout, DsnKeepoutType, DsnLayerType, DsnNet, DsnPadstack, DsnPcb, DsnPin, DsnRect,
+    DsnKeepout, DsnKeepoutType, DsnLayerType, DsnNet, DsnPadstack, DsnPcb, DsnPin, DsnPinRef,
     DsnRule, DsnShape, DsnSide,
 };
 use memegeom::geom::math::{eq, pt_eq};
 use memegeom::primitive::point::Pt;
-use memegeom::primitive::rect::Rt;
-use memegeom::primitive::{circ, path, poly, rt, ShapeOps};
+use memegeom::primitive::shape::Shape;
+use memegeom::primitive::{circ, path, poly, pt, rt, ShapeOps};
 use strum::IntoEnumIterator;
 
+use crate::dsn::extras::{
+    DsnExtraClearance, DsnExtraClearanceType, DsnExtraLayerType, DsnExtraMirror, DsnExtraShape,
+    DsnExtraSupply, DsnExtraVia, DsnExtraWire, DsnExtras,
+};
+use crate::error::MemerouteError;
 use crate::model::pcb::{
-    Clearance, Component, Keepout, KeepoutType, Layer, LayerId, LayerKind, LayerSet, LayerShape,
-    Net, ObjectKind, Padstack, Pcb, Pin, PinRef, Rule, RuleSet,
+    Clearance, Component, Designator, Keepout, KeepoutType, Layer, LayerId, LayerKind, LayerSet,
+    LayerShape, Mirror, Net, ObjectKind, Padstack, Pcb, Pin, PinRef, Plane, Rule, RuleSet, Supply,
+    Via, Wire,
 };
-use crate::name::Id;
+use crate::name::{Id, NO_ID};
+
+// Default maximum angle in radians between points when tessellating arcs.
+const DEFAULT_ARC_STEP: f64 = PI / 32.0;
+
+// Converts the geometry of a DSN shape, ignoring its layer. |scale| converts
+// DSN units to millimetres.
+pub fn dsn_shape_to_shape(v: &DsnShape, scale: f64) -> Result<Shape> {
+    convert_shape(v, &|v| scale * v, &|v| scale * v, DEFAULT_ARC_STEP)
+}
+
+// Returns points along the arc about |center| going counter-clockwise from
+// |start| to |end|, at most |step| radians apart. If |start| and |end| are the
+// same, this is a full circle.
+fn arc_pts(start: Pt, end: Pt, center: Pt, step: f64) -> Vec<Pt> {
+    let r = start.dist(center);
+    let a0 = (start.y - center.y).atan2(start.x - center.x);
+    let a1 = (end.y - center.y).atan2(end.x - center.x);
+    let mut sweep = (a1 - a0).rem_euclid(2.0 * PI);
+    if eq(sweep, 0.0) {
+        sweep = 2.0 * PI;
+    }
+    let n = (sweep / step).ceil().max(1.0) as usize;
+    (0..=n)
+        .map(|i| {
+            let a = a0 + sweep * i as f64 / n as f64;
+            pt(center.x + r * a.cos(), center.y + r * a.sin())
+        })
+        .collect()
+}
+
+fn polygon(mut pts: Vec<Pt>, aperture_width: f64) -> Result<Shape> {
+    // Polygons seem to have the first vertex repeated.
+    if pts.len() >= 2 && pt_eq(*pts.first().unwrap(), *pts.last().unwrap()) {
+        pts.pop();
+    }
+    if !eq(aperture_width, 0.0) {
+        return Err(MemerouteError::GeometryError(
+            "aperture width for polygons is unsupported".to_owned(),
+        )
+        .into());
+    }
+    Ok(poly(&pts).shape())
+}
+
+// Converts the geometry of a DSN shape, using |coord| to convert each coordinate
+// and |len| to convert widths and diameters. Arcs are tessellated into paths
+// with points at most |arc_step| radians apart.
+fn convert_shape(
+    v: &DsnShape,
+    coord: &dyn Fn(f64) -> f64,
+    len: &dyn Fn(f64) -> f64,
+    arc_step: f64,
+) -> Result<Shape> {
+    let pt = |v: Pt| Pt { x: coord(v.x), y: coord(v.y) };
+    Ok(match v {
+        DsnShape::Rect(v) => {
+            rt(coord(v.rect.l()), coord(v.rect.b()), coord(v.rect.r()), coord(v.rect.t())).shape()
+        }
+        // Circles without a position are at the origin of their local frame,
+        // so e.g. padstack circles end up centered on the pin once placed.
+        DsnShape::Circle(v) => circ(pt(v.p), len(v.diameter / 2.0)).shape(),
+        DsnShape::Polygon(v) => polygon(v.pts.iter().map(|&v| pt(v)).collect(), v.aperture_width)?,
+        DsnShape::Path(v) => {
+            path(&v.pts.iter().map(|&v| pt(v)).collect::<Vec<_>>(), len(v.aperture_width) / 2.0)
+                .shape()
+        }
+        DsnShape::QArc(v) => {
+            let pts = arc_pts(pt(v.start), pt(v.end), pt(v.center), arc_step);
+            path(&pts, len(v.aperture_width) / 2.0).shape()
+        }
+    })
+}
 
 #[must_use]
 #[derive(Debug, Clone)]
 pub struct DesignToPcb {
     dsn: DsnPcb,
+    extras: DsnExtras,
     pcb: Pcb,
     padstacks: HashMap<Id, Padstack>,
     images: HashMap<Id, Component>,
     layers: HashMap<Id, LayerId>,
+    quantize: bool,    // Round coordinates to the resolution of the design.
+    best_effort: bool, // Skip unsupported geometry instead of failing.
+    arc_step: f64,     // Maximum angle in radians between points on tessellated arcs.
+    diagnostics: RefCell<Vec<String>>,
 }
 
 impl DesignToPcb {
     pub fn new(dsn: DsnPcb) -> Self {
         Self {
             dsn,
+            extras: DsnExtras::default(),
             pcb: Pcb::default(),
             padstacks: HashMap::default(),
             images: HashMap::default(),
             layers: HashMap::default(),
+            quantize: false,
+            best_effort: false,
+            arc_step: DEFAULT_ARC_STEP,
+            diagnostics: RefCell::default(),
         }
     }
 
+    // Sets the parts of the design which memedsn doesn't parse, collected
+    // with |strip_extras|.
+    pub fn set_extras(&mut self, extras: DsnExtras) {
+        self.extras = extras;
+    }
+
+    // If set, unsupported geometry (e.g. polygons with an aperture) is skipped
+    // and recorded in the diagnostics of the resulting Pcb, rather than failing
+    // the whole conversion.
+    pub fn set_best_effort(&mut self, best_effort: bool) {
+        self.best_effort = best_effort;
+    }
+
+    // If set, coordinates are rounded to the design resolution on import, so
+    // coincident points compare equal.
+    pub fn set_quantize(&mut self, quantize: bool) {
+        self.quantize = quantize;
+    }
+
+    // Sets the maximum angle in radians between points when tessellating arcs.
+    pub fn set_arc_step(&mut self, arc_step: f64) {
+        self.arc_step = arc_step;
+    }
+
+    // Coordinates are in the unit of the design, which may differ from the
+    // unit of its resolution, e.g. (resolution um 1000) with (unit mm).
     fn mm(&self) -> f64 {
-        match self.dsn.resolution.dimension {
+        Self::unit_mm(&self.dsn.unit.dimension)
+    }
+
+    fn unit_mm(v: &DsnDimensionUnit) -> f64 {
+        match v {
             DsnDimensionUnit::Inch => 25.4,
             DsnDimensionUnit::Mil => 0.0254,
             DsnDimensionUnit::Cm => 10.0,
@@ -50,16 +171,20 @@ impl DesignToPcb {
     }
 
     fn coord(&self, v: f64) -> f64 {
-        self.mm() * v
+        let v = self.mm() * v;
+        if self.quantize {
+            let step =
+                Self::unit_mm(&self.dsn.resolution.dimension) / self.dsn.resolution.amount as f64;
+            (v / step).round() * step
+        } else {
+            v
+        }
     }
 
-    fn rect(&self, v: &DsnRect) -> Rt {
-        rt(
-            self.coord(v.rect.l()),
-            self.coord(v.rect.b()),
-            self.coord(v.rect.r()),
-            self.coord(v.rect.t()),
-        )
+    // Widths, radii and other lengths aren't quantized, since that would change
+    // the size of shapes rather than just line up coincident points.
+    fn len(&self, v: f64) -> f64 {
+        self.mm() * v
     }
 
     fn pt(&self, v: Pt) -> Pt {
@@ -67,7 +192,10 @@ impl DesignToPcb {
     }
 
     fn rot(r: f64) -> f64 {
-        r
+        // Session output only has two decimal places for rotations, so normalize
+        // to that and to [0, 360) so import and export agree. Adding zero turns
+        // -0.0 into 0.0, so it isn't written out as "-0.00".
+        ((r * 100.0).round() / 100.0).rem_euclid(360.0) + 0.0
     }
 
     fn layers(&self, name: &str) -> Result<LayerSet> {
@@ -86,51 +214,147 @@ impl DesignToPcb {
         })
     }
 
+    fn shape_layer_id(v: &DsnShape) -> &str {
+        match v {
+            DsnShape::Rect(v) => &v.layer_id,
+            DsnShape::Circle(v) => &v.layer_id,
+            DsnShape::Polygon(v) => &v.layer_id,
+            DsnShape::Path(v) => &v.layer_id,
+            DsnShape::QArc(v) => &v.layer_id,
+        }
+    }
+
     fn shape(&self, v: &DsnShape) -> Result<LayerShape> {
+        Ok(LayerShape {
+            layers: self.layers(Self::shape_layer_id(v))?,
+            shape: convert_shape(v, &|v| self.coord(v), &|v| self.len(v), self.arc_step)?,
+        })
+    }
+
+    // Converts the geometry of a shape, ignoring its layer.
+    fn extra_geom(&self, v: &DsnExtraShape) -> Result<Shape> {
         Ok(match v {
-            DsnShape::Rect(v) => {
-                LayerShape { layers: self.layers(&v.layer_id)?, shape: self.rect(v).shape() }
+            DsnExtraShape::Rect { l, b, r, t, .. } => {
+                rt(self.coord(*l), self.coord(*b), self.coord(*r), self.coord(*t)).shape()
             }
-            DsnShape::Circle(v) => LayerShape {
-                layers: self.layers(&v.layer_id)?,
-                shape: circ(self.pt(v.p), self.coord(v.diameter / 2.0)).shape(),
-            },
-            DsnShape::Polygon(v) => {
-                let mut pts: Vec<Pt> = v.pts.iter().map(|&v| self.pt(v)).collect();
-                // Polygons seem to have the first vertex repeated.
-                if pts.len() >= 2 && pt_eq(*pts.first().unwrap(), *pts.last().unwrap()) {
-                    pts.pop();
-                }
-                assert!(eq(v.aperture_width, 0.0), "aperture width for polygons is unsupported");
-                LayerShape { layers: self.layers(&v.layer_id)?, shape: poly(&pts).shape() }
+            DsnExtraShape::Circle { diameter, p, .. } => {
+                circ(self.pt(*p), self.len(diameter / 2.0)).shape()
+            }
+            DsnExtraShape::Polygon { aperture_width, pts, .. } => {
+                polygon(pts.iter().map(|&v| self.pt(v)).collect(), *aperture_width)?
+            }
+            DsnExtraShape::Path { aperture_width, pts, .. } => {
+                let pts: Vec<Pt> = pts.iter().map(|&v| self.pt(v)).collect();
+                path(&pts, self.len(*aperture_width) / 2.0).shape()
             }
-            DsnShape::Path(v) => LayerShape {
-                layers: self.layers(&v.layer_id)?,
-                shape: path(
-                    &v.pts.iter().map(|&v| self.pt(v)).collect::<Vec<_>>(),
-                    self.coord(v.aperture_width) / 2.0,
-                )
-                .shape(),
-            },
-            DsnShape::QArc(_v) => todo!(),
         })
     }
 
-    fn keepout(&self, v: &DsnKeepout) -> Result<Keepout> {
-        Ok(Keepout {
+    fn extra_shape(&self, v: &DsnExtraShape) -> Result<LayerShape> {
+        Ok(LayerShape { layers: self.layers(v.layer_id())?, shape: self.extra_geom(v)? })
+    }
+
+    // In best effort mode, records unsupported geometry errors as diagnostics
+    // and returns None, so the caller can skip it. Other errors are returned.
+    fn supported<T>(&self, ctx: &str, r: Result<T>) -> Result<Option<T>> {
+        match r {
+            Err(e)
+                if self.best_effort
+                    && matches!(
+                        e.downcast_ref::<MemerouteError>(),
+                        Some(MemerouteError::GeometryError(_))
+                    ) =>
+            {
+                self.diagnostics.borrow_mut().push(format!("{ctx}: {e}"));
+                Ok(None)
+            }
+            r => r.map(Some),
+        }
+    }
+
+    fn shapes<'a>(
+        &self,
+        ctx: &str,
+        vs: impl Iterator<Item = &'a DsnShape>,
+    ) -> Result<Vec<LayerShape>> {
+        let mut res = Vec::new();
+        for v in vs {
+            res.extend(self.supported(ctx, self.shape(v))?);
+        }
+        Ok(res)
+    }
+
+    // Checks that every layer referenced by a shape exists, reporting all missing
+    // layers at once.
+    fn validate_layer_refs(&self) -> Result<()> {
+        let lib = &self.dsn.library;
+        let structure = &self.dsn.structure;
+        let shapes = lib
+            .padstacks
+            .iter()
+            .flat_map(|v| v.shapes.iter().map(|s| &s.shape))
+            .chain(lib.images.iter().flat_map(|v| v.outlines.iter()))
+            .chain(lib.images.iter().flat_map(|v| v.keepouts.iter().map(|k| &k.shape)))
+            .chain(structure.boundaries.iter())
+            .chain(structure.keepouts.iter().map(|k| &k.shape));
+        let missing: Vec<_> = shapes
+            .map(Self::shape_layer_id)
+            .filter(|name| self.layers(name).is_err())
+            .sorted()
+            .dedup()
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(eyre!("unknown layers referenced: {}", missing.join(", ")))
+        }
+    }
+
+    fn keepout(
+        &self,
+        ctx: &str,
+        v: &DsnKeepout,
+        windows: &[DsnExtraShape],
+    ) -> Result<Option<Keepout>> {
+        let Some(shape) = self.supported(ctx, self.shape(&v.shape))? else { return Ok(None) };
+        let mut window_shapes = Vec::new();
+        for w in windows {
+            window_shapes.extend(self.supported(ctx, self.extra_geom(w))?);
+        }
+        Ok(Some(Keepout {
             kind: match v.keepout_type {
                 DsnKeepoutType::Keepout => KeepoutType::Keepout,
                 DsnKeepoutType::ViaKeepout => KeepoutType::ViaKeepout,
                 DsnKeepoutType::WireKeepout => KeepoutType::WireKeepout,
             },
-            shape: self.shape(&v.shape)?,
-        })
+            shape,
+            windows: window_shapes,
+        }))
+    }
+
+    // Keepouts in the image |image_id|, or in the structure if None.
+    fn keepouts(
+        &self,
+        ctx: &str,
+        image_id: Option<&str>,
+        vs: &[DsnKeepout],
+    ) -> Result<Vec<Keepout>> {
+        let mut res = Vec::new();
+        for (idx, v) in vs.iter().enumerate() {
+            let key = (image_id.map(str::to_owned), idx);
+            let windows = self.extras.keepout_windows.get(&key).map_or(&[][..], Vec::as_slice);
+            res.extend(self.keepout(ctx, v, windows)?);
+        }
+        Ok(res)
     }
 
     fn padstack(&self, v: &DsnPadstack) -> Result<Padstack> {
         Ok(Padstack {
             id: self.pcb.to_id(&v.padstack_id),
-            shapes: v.shapes.iter().map(|s| self.shape(&s.shape)).collect::<Result<_>>()?,
+            shapes: self.shapes(
+                &format!("padstack {}", v.padstack_id),
+                v.shapes.iter().map(|s| &s.shape),
+            )?,
             attach: v.attach,
         })
     }
@@ -151,8 +375,9 @@ impl DesignToPcb {
     fn image(&self, v: &DsnImage) -> Result<Component> {
         let mut c = Component::default();
         c.footprint_id = self.pcb.to_id(&v.image_id);
-        c.outlines = v.outlines.iter().map(|p| self.shape(p)).collect::<Result<_>>()?;
-        c.keepouts = v.keepouts.iter().map(|p| self.keepout(p)).collect::<Result<_>>()?;
+        let ctx = format!("image {}", v.image_id);
+        c.outlines = self.shapes(&ctx, v.outlines.iter())?;
+        c.keepouts = self.keepouts(&ctx, Some(v.image_id.as_str()), &v.keepouts)?;
         for pin in &v.pins {
             c.add_pin(self.pin(pin)?);
         }
@@ -170,27 +395,70 @@ impl DesignToPcb {
             c.id = self.pcb.to_id(&pl.component_id);
             c.p = self.pt(pl.p);
             c.rotation = Self::rot(pl.rotation);
+            // DSN has no separate designator location, so anchor it at the placement.
+            c.designator = Some(Designator { p: c.p, rotation: c.rotation });
             match pl.side {
                 DsnSide::Front => {}
                 DsnSide::Back => c.flip(self.pcb.layers().len()),
                 DsnSide::Both => return Err(eyre!("invalid side specification")),
             };
+            c.mirror = match self.extras.mirrors.get(&pl.component_id) {
+                None => Mirror::None,
+                Some(DsnExtraMirror::X) => Mirror::X,
+                Some(DsnExtraMirror::Y) => Mirror::Y,
+                Some(DsnExtraMirror::XY) => Mirror::XY,
+            };
             components.push(c);
         }
         Ok(components)
     }
 
+    // Wires and vias without a net don't connect to anything.
+    fn net_id(&self, v: Option<&str>) -> Id {
+        v.map_or(NO_ID, |v| self.pcb.to_id(v))
+    }
+
+    fn wire(&self, v: &DsnExtraWire) -> Result<Option<Wire>> {
+        let Some(shape) = self.supported("wire", self.extra_shape(&v.shape))? else {
+            return Ok(None);
+        };
+        Ok(Some(Wire { shape, net_id: self.net_id(v.net_id.as_deref()) }))
+    }
+
+    fn vias(&self, v: &DsnExtraVia) -> Result<Vec<Via>> {
+        let padstack = self
+            .padstacks
+            .get(&self.pcb.to_id(&v.padstack_id))
+            .ok_or_else(|| eyre!("unknown via padstack id {}", v.padstack_id))?;
+        let net_id = self.net_id(v.net_id.as_deref());
+        Ok(v.pts
+            .iter()
+            .map(|&p| Via { p: self.pt(p), padstack: padstack.clone(), net_id })
+            .collect())
+    }
+
     fn net(&self, v: &DsnNet) -> Net {
+        let pin_ref = |p: &DsnPinRef| PinRef {
+            component: self.pcb.to_id(&p.component_id),
+            pin: self.pcb.to_id(&p.pin_id),
+        };
+        // Pins from (order ...) come first, in that order, then the rest.
+        let name = |p: &DsnPinRef| format!("{}-{}", p.component_id, p.pin_id);
+        let order = self.extras.nets.get(&v.net_id).map(|n| n.order.as_slice()).unwrap_or_default();
+        let pins = order
+            .iter()
+            .filter_map(|o| v.pins.iter().find(|p| name(p) == *o))
+            .chain(v.pins.iter().filter(|p| !order.contains(&name(p))))
+            .map(pin_ref)
+            .collect();
         Net {
             id: self.pcb.to_id(&v.net_id),
-            pins: v
-                .pins
-                .iter()
-                .map(|p| PinRef {
-                    component: self.pcb.to_id(&p.component_id),
-                    pin: self.pcb.to_id(&p.pin_id),
-                })
-                .collect(),
+            pins,
+            fixed: self.extras.is_net_fixed(&v.net_id),
+            supply: self.extras.nets.get(&v.net_id).and_then(|v| v.supply).map(|v| match v {
+                DsnExtraSupply::Power => Supply::Power,
+                DsnExtraSupply::Ground => Supply::Ground,
+            }),
         }
     }
 
@@ -203,17 +471,45 @@ impl DesignToPcb {
         }
     }
 
+    // Clearances are only checked against other nets, so same net types like
+    // via_via_same_net apply between those kinds on different nets instead.
+    // Turn gaps are approximated by the clearance to the whole wire.
+    fn extra_clearance_type(v: DsnExtraClearanceType) -> Vec<(ObjectKind, ObjectKind)> {
+        match v {
+            DsnExtraClearanceType::DefaultSmd => {
+                Self::clearance_type(&DsnClearanceType::DefaultSmd)
+            }
+            DsnExtraClearanceType::SmdSmd => Self::clearance_type(&DsnClearanceType::SmdSmd),
+            DsnExtraClearanceType::SmdViaSameNet | DsnExtraClearanceType::ViaAtSmd => {
+                vec![(ObjectKind::Smd, ObjectKind::Via)]
+            }
+            DsnExtraClearanceType::ViaViaSameNet => vec![(ObjectKind::Via, ObjectKind::Via)],
+            DsnExtraClearanceType::SmdToTurnGap => vec![(ObjectKind::Smd, ObjectKind::Wire)],
+            DsnExtraClearanceType::PadToTurnGap => {
+                vec![(ObjectKind::Pin, ObjectKind::Wire), (ObjectKind::Smd, ObjectKind::Wire)]
+            }
+            DsnExtraClearanceType::AntipadGap => {
+                vec![(ObjectKind::Area, ObjectKind::Via), (ObjectKind::Area, ObjectKind::Pin)]
+            }
+        }
+    }
+
     fn clearance(&self, v: &DsnClearance) -> Clearance {
         let pairs = v.types.iter().fold(vec![], |mut a, b| {
             a.extend(Self::clearance_type(b));
             a
         });
-        Clearance::new(self.coord(v.amount), &pairs)
+        Clearance::new(self.len(v.amount), &pairs)
+    }
+
+    fn extra_clearance(&self, v: &DsnExtraClearance) -> Clearance {
+        let pairs: Vec<_> = v.types.iter().flat_map(|&t| Self::extra_clearance_type(t)).collect();
+        Clearance::new(self.len(v.amount), &pairs)
     }
 
     fn rule(&self, v: &DsnRule) -> Rule {
         match v {
-            DsnRule::Width(w) => Rule::Radius(self.coord(*w) / 2.0),
+            DsnRule::Width(w) => Rule::Radius(self.len(*w) / 2.0),
             DsnRule::Clearance(c) => Rule::Clearance(self.clearance(c)),
         }
     }
@@ -228,6 +524,14 @@ impl DesignToPcb {
         let id = self.pcb.to_id(&v.class_id);
         let mut rules: Vec<Rule> = v.rules.iter().map(|r| self.rule(r)).collect();
         rules.extend(v.circuits.iter().map(|c| self.circuit(c)));
+        if let Some(class) = self.extras.classes.get(&v.class_id) {
+            rules.extend(class.clearances.iter().map(|c| Rule::Clearance(self.extra_clearance(c))));
+            if !class.use_layers.is_empty() {
+                let layers: LayerSet =
+                    class.use_layers.iter().map(|v| self.layers(v)).collect::<Result<_>>()?;
+                rules.push(Rule::UseLayers(layers));
+            }
+        }
         RuleSet::new(id, rules)
     }
 
@@ -249,27 +553,43 @@ impl DesignToPcb {
         Ok(())
     }
 
-    pub fn convert(mut self) -> Result<Pcb> {
+    pub fn convert(self) -> std::result::Result<Pcb, MemerouteError> {
+        self.convert_pcb()
+            .map_err(|e| MemerouteError::from_report(e, MemerouteError::ConversionError))
+    }
+
+    fn convert_pcb(mut self) -> Result<Pcb> {
         self.pcb.set_pcb_name(&self.dsn.pcb_id);
-        if self.dsn.unit.dimension != self.dsn.resolution.dimension {
-            return Err(eyre!(
-                "unit override unimplemented: {} {}",
-                self.dsn.unit.dimension,
-                self.dsn.resolution.dimension
-            ));
-        }
 
-        // Layers needed for padstacks and images.
-        for (id, v) in self.dsn.structure.layers.iter().enumerate() {
-            let id = id as LayerId;
+        // Layers needed for padstacks and images. Layers are declared in physical
+        // stackup order, top first, unless they have an index property giving
+        // their position. Ids are the stackup position, so duplicate or missing
+        // indices are caught by |validate_layers|.
+        let mut dsn_layers: Vec<_> = self
+            .dsn
+            .structure
+            .layers
+            .iter()
+            .enumerate()
+            .map(|(idx, v)| {
+                let index = self.extras.layers.get(&v.layer_name).and_then(|l| l.index);
+                (index.unwrap_or(idx), v)
+            })
+            .collect();
+        dsn_layers.sort_by_key(|&(id, _)| id);
+        for (id, v) in dsn_layers {
             if self.layers.insert(self.pcb.to_id(&v.layer_name), id).is_some() {
                 return Err(eyre!("duplicate layer with id {}", v.layer_name));
             }
-            let kind = match v.layer_type {
-                DsnLayerType::Signal => LayerKind::Signal,
-                DsnLayerType::Power => LayerKind::Power,
-                DsnLayerType::Mixed => LayerKind::Mixed,
-                DsnLayerType::Jumper => LayerKind::Jumper,
+            let extra_type = self.extras.layers.get(&v.layer_name).and_then(|l| l.layer_type);
+            let kind = match (extra_type, &v.layer_type) {
+                (Some(DsnExtraLayerType::SolderMask), _) => LayerKind::SolderMask,
+                (Some(DsnExtraLayerType::Paste), _) => LayerKind::Paste,
+                (Some(DsnExtraLayerType::Silk), _) => LayerKind::Silk,
+                (None, DsnLayerType::Signal) => LayerKind::Signal,
+                (None, DsnLayerType::Power) => LayerKind::Power,
+                (None, DsnLayerType::Mixed) => LayerKind::Mixed,
+                (None, DsnLayerType::Jumper) => LayerKind::Jumper,
             };
             self.pcb.add_layer(Layer {
                 name_id: self.pcb.to_id(&v.layer_name),
@@ -278,17 +598,56 @@ impl DesignToPcb {
             });
         }
 
+        self.pcb.validate_layers()?;
+        self.validate_layer_refs()?;
+
+        // Width rules from layers and the structure are fallbacks for class rules.
+        for v in &self.dsn.structure.layers {
+            let id = *self.layers.get(&self.pcb.to_id(&v.layer_name)).unwrap();
+            for rule in &v.rules {
+                if let DsnRule::Width(w) = rule {
+                    self.pcb.set_layer_radius(id, self.len(*w) / 2.0);
+                }
+            }
+        }
+        for rule in &self.dsn.structure.rules {
+            if let DsnRule::Width(w) = rule {
+                self.pcb.set_default_radius(self.len(*w) / 2.0);
+            }
+        }
+
+        if let Some(grid) = self.extras.wire_grid {
+            if grid <= 0.0 {
+                return Err(eyre!("wire grid must be positive, got {}", grid));
+            }
+            self.pcb.set_wire_grid(self.len(grid));
+        }
+
         self.convert_padstacks()?; // Padstacks are used in images.
         self.convert_images()?;
 
         // Physical structure:
         for v in &self.dsn.structure.boundaries {
             // Convert boundaries to closed shapes.
-            let LayerShape { layers, shape } = self.shape(v)?;
-            self.pcb.add_boundary(LayerShape { layers, shape: shape.filled() });
+            if let Some(LayerShape { layers, shape }) = self.supported("boundary", self.shape(v))? {
+                self.pcb.add_boundary(LayerShape { layers, shape: shape.filled() });
+            }
         }
-        for v in &self.dsn.structure.keepouts {
-            self.pcb.add_keepout(self.keepout(v)?);
+        for v in self.keepouts("structure", None, &self.dsn.structure.keepouts)? {
+            self.pcb.add_keepout(v);
+        }
+        for v in &self.extras.planes {
+            let Some(LayerShape { layers, shape }) =
+                self.supported("plane", self.extra_shape(&v.shape))?
+            else {
+                continue;
+            };
+            let mut windows = Vec::new();
+            for w in &v.windows {
+                windows.extend(self.supported("plane", self.extra_geom(w))?);
+            }
+            let shape = LayerShape { layers, shape: shape.filled() };
+            self.pcb.add_plane(Plane { net_id: self.pcb.to_id(&v.net_id), shape, windows });
         }
         for v in &self.dsn.structure.vias {
             self.pcb.add_via_padstack(
@@ -321,10 +680,376 @@ impl DesignToPcb {
             }
         }
 
-        // TODO: Add wires
-        // TODO: Add vias
+        for (a, b) in &self.extras.pairs {
+            let (a, b) = (self.pcb.to_id(a), self.pcb.to_id(b));
+            for id in [a, b] {
+                if self.pcb.net(id).is_none() {
+                    return Err(eyre!("unknown net {} in pair", self.pcb.to_name(id)));
+                }
+            }
+            self.pcb.add_diff_pair(a, b);
+        }
+
+        for v in &self.extras.wires {
+            if let Some(wire) = self.wire(v)? {
+                self.pcb.add_wire(wire);
+            }
+        }
+        for v in &self.extras.vias {
+            for via in self.vias(v)? {
+                self.pcb.add_via(via);
+            }
+        }
+
+        for d in self.diagnostics.take() {
+            self.pcb.add_diagnostic(d);
+        }
+
         // TODO: Support classes for nets.
-        // TODO: Support rules from structure.
         Ok(self.pcb)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use memedsn::lexer::Lexer;
+    use memedsn::parser::Parser;
+    use memegeom::geom::math::{eq, pt_eq};
+    use memegeom::primitive::shape::Shape;
+    use memegeom::primitive::{pt, ShapeOps};
+
+    use crate::dsn::design_to_pcb::DesignToPcb;
+    use crate::dsn::extras::strip_extras;
+    use crate::dsn::load_dsn;
+    use crate::error::MemerouteError;
+    use crate::model::pcb::{LayerKind, LayerSet, Mirror, ObjectKind, Pcb, PinRef, Supply};
+    use crate::route::drc::antipad_violations;
+    use crate::route::place_model::PlaceModel;
+    use crate::testing::{test_pcb, TEST_DSN};
+
+    #[test]
+    fn circle_without_position_is_centered_on_pin() {
+        let pcb = test_pcb();
+        let pin = PinRef { component: pcb.to_id("R1"), pin: pcb.to_id("1") };
+        let shapes = pcb.pin_world_shapes().remove(&pin).unwrap();
+        assert_eq!(shapes.len(), 1);
+        let b = shapes[0].shape.bounds();
+        // R1 is at (5, 10) rotated by 90 degrees, and its pin is 1 mm to the right.
+        assert!(eq((b.l() + b.r()) / 2.0, 5.0), "{b:?}");
+        assert!(eq((b.b() + b.t()) / 2.0, 11.0), "{b:?}");
+        assert!(eq(b.r() - b.l(), 1.0), "{b:?}");
+    }
+
+    #[test]
+    fn layers_and_structure() {
+        let pcb = test_pcb();
+        let layers: Vec<_> = pcb.layers().iter().map(|l| pcb.to_name(l.name_id)).collect();
+        assert_eq!(layers, ["F.Cu", "B.Cu"]);
+        assert!(pcb.layers().iter().enumerate().all(|(i, l)| l.layer_id == i));
+        assert!(pcb.layers().iter().all(|l| l.kind == LayerKind::Signal));
+        assert_eq!(pcb.boundaries().len(), 1);
+        let b = pcb.boundaries()[0].shape.bounds();
+        assert!(eq(b.l(), 0.0) && eq(b.b(), 0.0) && eq(b.r(), 20.0) && eq(b.t(), 20.0), "{b:?}");
+        assert_eq!(pcb.via_padstacks().len(), 1);
+        assert_eq!(pcb.via_padstacks()[0].id, pcb.to_id("via600"));
+        assert_eq!(pcb.via_padstacks()[0].shapes.len(), 2);
+    }
+
+    #[test]
+    fn placement() {
+        let pcb = test_pcb();
+        let r1 = pcb.component(pcb.to_id("R1")).unwrap();
+        assert_eq!(r1.footprint_id, pcb.to_id("pad_image"));
+        assert!(eq(r1.p.x, 5.0) && eq(r1.p.y, 10.0), "{:?}", r1.p);
+        assert!(eq(r1.rotation, 90.0));
+        let r2 = pcb.component(pcb.to_id("R2")).unwrap();
+        assert!(eq(r2.p.x, 15.0) && eq(r2.p.y, 10.0), "{:?}", r2.p);
+        assert!(eq(r2.rotation, 0.0));
+    }
+
+    #[test]
+    fn nets_and_classes() {
+        let pcb = test_pcb();
+        let net_id = pcb.to_id("A");
+        let net = pcb.net(net_id).unwrap();
+        let pins = [
+            PinRef { component: pcb.to_id("R1"), pin: pcb.to_id("1") },
+            PinRef { component: pcb.to_id("R2"), pin: pcb.to_id("1") },
+        ];
+        assert_eq!(net.pins, pins);
+        assert_eq!(pcb.net_ruleset(net_id).id, pcb.to_id("wide"));
+        assert!(eq(pcb.net_radius(net_id, 0).unwrap(), 0.2));
+        assert!(eq(pcb.net_radius(net_id, 1).unwrap(), 0.2));
+
+        let rules = pcb.resolved_rules(net_id);
+        assert_eq!(rules.use_via, Some(pcb.to_id("via600")));
+        // The clearance comes from the default class.
+        assert!(!rules.clearances.is_empty());
+        assert!(rules.clearances.iter().all(|c| eq(c.amount(), 0.2)));
+
+        // Nets not in a class use the default class.
+        assert_eq!(pcb.net_ruleset(pcb.to_id("B")).id, pcb.to_id("default"));
+        assert!(eq(pcb.net_radius(pcb.to_id("B"), 0).unwrap(), 0.1));
+        assert!(eq(pcb.net_radius(pcb.to_id("unknown"), 0).unwrap(), 0.1));
+    }
+
+    #[test]
+    fn unit_separate_from_resolution() {
+        // Coordinates are in the design unit, not the unit of the resolution.
+        let dsn = TEST_DSN
+            .replace("(resolution um 10)", "(resolution um 1000)")
+            .replace("(unit um)", "(unit mm)");
+        let pcb = load_dsn(&dsn).unwrap();
+        let r1 = pcb.component(pcb.to_id("R1")).unwrap();
+        assert!(eq(r1.p.x, 5000.0) && eq(r1.p.y, 10000.0), "{:?}", r1.p);
+        assert!(eq(pcb.net_radius(pcb.to_id("A"), 0).unwrap(), 200.0));
+    }
+
+    #[test]
+    fn missing_padstack_is_conversion_error() {
+        let dsn = TEST_DSN.replace("(pin round 1 1000 0)", "(pin missing 1 1000 0)");
+        let err = load_dsn(&dsn).unwrap_err();
+        assert!(matches!(&err, MemerouteError::ConversionError(msg) if msg.contains("missing")));
+    }
+
+    #[test]
+    fn unknown_layers_reported_together() {
+        let dsn = TEST_DSN
+            .replace("(circle F.Cu 1000)", "(circle In1.Cu 1000)")
+            .replace("(circle B.Cu 600)", "(circle In2.Cu 600)");
+        let err = load_dsn(&dsn).unwrap_err();
+        assert!(err.to_string().contains("unknown layers referenced: In1.Cu, In2.Cu"), "{err}");
+    }
+
+    #[test]
+    fn structure_width_is_default() {
+        // Net B is in the default class, which has no width rule here.
+        let dsn = TEST_DSN
+            .replace("(via via600)\n    (rule (width 200)", "(via via600)\n    (rule (width 300)")
+            .replace("(rule (width 200) (clearance 200)))", "(rule (clearance 200)))");
+        let pcb = load_dsn(&dsn).unwrap();
+        assert!(eq(pcb.net_radius(pcb.to_id("B"), 0).unwrap(), 0.15));
+        assert!(eq(pcb.net_radius(pcb.to_id("A"), 0).unwrap(), 0.2));
+    }
+
+    #[test]
+    fn wiring_wires_and_vias() {
+        let dsn = TEST_DSN.replace(
+            "(wiring)",
+            "(wiring
+    (wire (path F.Cu 200 6000 5000 10000 5000) (net B) (type route))
+    (via via600 10000 5000 (net B))
+  )",
+        );
+        let pcb = load_dsn(&dsn).unwrap();
+        let net_id = pcb.to_id("B");
+        let wires: Vec<_> = pcb.net_wires(net_id).collect();
+        assert_eq!(wires.len(), 1);
+        assert_eq!(wires[0].shape.layers, LayerSet::one(0));
+        assert!(eq(wires[0].length(), 4.0));
+        let vias: Vec<_> = pcb.net_vias(net_id).collect();
+        assert_eq!(vias.len(), 1);
+        assert!(pt_eq(vias[0].p, pt(10.0, 5.0)), "{:?}", vias[0].p);
+        assert_eq!(vias[0].padstack.id, pcb.to_id("via600"));
+    }
+
+    #[test]
+    fn keepout_window_allows_routing_inside() {
+        let dsn = TEST_DSN.replace(
+            "(boundary (rect pcb 0 0 20000 20000))",
+            "(boundary (rect pcb 0 0 20000 20000))
+    (keepout (rect F.Cu 8000 8000 12000 12000) (window (rect F.Cu 9000 9000 11000 11000)))",
+        );
+        let pcb = load_dsn(&dsn).unwrap();
+        assert_eq!(pcb.keepouts().len(), 1);
+        assert_eq!(pcb.keepouts()[0].windows.len(), 1);
+        let net_id = pcb.to_id("B");
+        let place = PlaceModel::new(pcb);
+        let inside = place.create_wire(net_id, 0, &[pt(9.5, 10.0), pt(10.5, 10.0)]).unwrap();
+        assert!(!place.is_wire_blocked(&inside));
+        let across = place.create_wire(net_id, 0, &[pt(7.0, 10.0), pt(10.0, 10.0)]).unwrap();
+        assert!(place.is_wire_blocked(&across));
+    }
+
+    #[test]
+    fn net_supply() {
+        let dsn = TEST_DSN.replace("(net B (pins", "(net B (supply ground) (pins");
+        let pcb = load_dsn(&dsn).unwrap();
+        assert_eq!(pcb.net(pcb.to_id("B")).unwrap().supply(), Some(Supply::Ground));
+        assert!(pcb.net(pcb.to_id("B")).unwrap().is_ground());
+        assert_eq!(pcb.net(pcb.to_id("A")).unwrap().supply(), None);
+    }
+
+    #[test]
+    fn layer_index_orders_stackup() {
+        // B.Cu is declared first, but F.Cu is on top.
+        let dsn = TEST_DSN
+            .replace(
+                "(layer F.Cu (type signal))\n    (layer B.Cu (type signal))",
+                "(layer B.Cu (type signal) (property (index 1)))
+    (layer F.Cu (type signal) (property (index 0)))",
+            )
+            .replace("(place R4 15000 5000 front 0)", "(place R4 15000 5000 back 0)");
+        let pcb = load_dsn(&dsn).unwrap();
+        let layers: Vec<_> = pcb.layers().iter().map(|l| pcb.to_name(l.name_id)).collect();
+        assert_eq!(layers, ["F.Cu", "B.Cu"]);
+        let pad_layers = |c: &str| {
+            let pin = PinRef { component: pcb.to_id(c), pin: pcb.to_id("1") };
+            pcb.pin_world_shapes().remove(&pin).unwrap()[0].layers
+        };
+        // Flipping R4 to the back moves its F.Cu pad to B.Cu.
+        assert_eq!(pad_layers("R3"), LayerSet::one(0));
+        assert_eq!(pad_layers("R4"), LayerSet::one(1));
+    }
+
+    #[test]
+    fn bad_layer_indices_rejected() {
+        let dsn = |f: usize, b: usize| {
+            TEST_DSN
+                .replace(
+                    "(layer F.Cu (type signal))",
+                    &format!("(layer F.Cu (type signal) (property (index {f})))"),
+                )
+                .replace(
+                    "(layer B.Cu (type signal))",
+                    &format!("(layer B.Cu (type signal) (property (index {b})))"),
+                )
+        };
+        let err = load_dsn(&dsn(0, 2)).unwrap_err();
+        assert!(err.to_string().contains("not contiguous, missing id 1"), "{err}");
+        let err = load_dsn(&dsn(1, 1)).unwrap_err();
+        assert!(err.to_string().contains("F.Cu and B.Cu both have id 1"), "{err}");
+    }
+
+    #[test]
+    fn mask_layer_is_not_copper() {
+        let dsn = TEST_DSN
+            .replace(
+                "(layer B.Cu (type signal))",
+                "(layer B.Cu (type signal))\n    (layer F.Mask (type mask))",
+            )
+            .replace(
+                "(circle F.Cu 1000))",
+                "(circle F.Cu 1000))\n      (shape (circle F.Mask 3000))",
+            );
+        let pcb = load_dsn(&dsn).unwrap();
+        assert_eq!(pcb.layers()[2].kind, LayerKind::SolderMask);
+        assert_eq!(pcb.copper_layers(), [0, 1].into_iter().collect::<LayerSet>());
+        // The mask opening is kept for rendering, but doesn't block wires.
+        let pin = PinRef { component: pcb.to_id("R3"), pin: pcb.to_id("1") };
+        assert_eq!(pcb.pin_world_shapes().remove(&pin).unwrap().len(), 2);
+        let net_id = pcb.to_id("A");
+        let place = PlaceModel::new(pcb);
+        // R3's pad is at (6, 5), and its mask opening has radius 1.5.
+        let wire = place.create_wire(net_id, 0, &[pt(4.0, 6.2), pt(8.0, 6.2)]).unwrap();
+        assert!(!place.is_wire_blocked(&wire));
+    }
+
+    #[test]
+    fn mirror() {
+        let dsn = TEST_DSN
+            .replace("(place R2 15000 10000 front 0)", "(place R2 15000 10000 front 0 (mirror y))");
+        let pcb = load_dsn(&dsn).unwrap();
+        assert_eq!(pcb.component(pcb.to_id("R2")).unwrap().mirror, Mirror::Y);
+        assert_eq!(pcb.component(pcb.to_id("R3")).unwrap().mirror, Mirror::None);
+        // Mirroring about the y axis moves R2's pin to the left of it.
+        let pin = PinRef { component: pcb.to_id("R2"), pin: pcb.to_id("1") };
+        let b = pcb.pin_world_shapes().remove(&pin).unwrap()[0].shape.bounds();
+        assert!(eq((b.l() + b.r()) / 2.0, 14.0), "{b:?}");
+        assert!(eq((b.b() + b.t()) / 2.0, 10.0), "{b:?}");
+    }
+
+    #[test]
+    fn net_order() {
+        let dsn = TEST_DSN.replace("(pins R1-1 R2-1)", "(pins R1-1 R2-1) (order R2-1 R1-1)");
+        let pcb = load_dsn(&dsn).unwrap();
+        let pin = |c: &str| PinRef { component: pcb.to_id(c), pin: pcb.to_id("1") };
+        assert_eq!(pcb.net(pcb.to_id("A")).unwrap().pins, [pin("R2"), pin("R1")]);
+        assert_eq!(pcb.net(pcb.to_id("B")).unwrap().pins, [pin("R3"), pin("R4")]);
+    }
+
+    #[test]
+    fn plane() {
+        let dsn = TEST_DSN
+            .replace(
+                "(via via600)",
+                "(via via600)
+    (plane B (rect B.Cu 0 0 20000 20000) (window (circle B.Cu 1200 10000 15000)))",
+            )
+            .replace(
+                "(wiring)",
+                "(wiring (via via600 10000 15000 (net A)) (via via600 5000 15000 (net A)))",
+            );
+        let pcb = load_dsn(&dsn).unwrap();
+        assert_eq!(pcb.planes().len(), 1);
+        let plane = &pcb.planes()[0];
+        assert_eq!(plane.net_id, pcb.to_id("B"));
+        assert_eq!(plane.shape.layers, LayerSet::one(1));
+        let b = plane.shape.shape.bounds();
+        assert!(eq(b.l(), 0.0) && eq(b.b(), 0.0) && eq(b.r(), 20.0) && eq(b.t(), 20.0), "{b:?}");
+        assert_eq!(plane.windows.len(), 1);
+        // Only the via outside the window is too close to the plane.
+        let violations = antipad_violations(&pcb, 0.2);
+        assert_eq!(violations.len(), 1, "{violations:?}");
+        assert_eq!(violations[0].via_idx, 1);
+        assert_eq!(violations[0].layer, 1);
+    }
+
+    #[test]
+    fn clearance_types() {
+        let dsn = TEST_DSN.replace(
+            "(rule (width 400))",
+            "(rule (width 400) (clearance 300 (type smd_via_same_net))
+      (clearance 500 (type smd_smd)))",
+        );
+        let pcb = load_dsn(&dsn).unwrap();
+        let clearances = pcb.net_ruleset(pcb.to_id("A")).clearances();
+        let c = |amount: f64| clearances.iter().find(|c| eq(c.amount(), amount)).unwrap();
+        assert!(c(0.3).applies(ObjectKind::Smd, ObjectKind::Via));
+        assert!(c(0.3).applies(ObjectKind::Via, ObjectKind::Smd));
+        assert!(!c(0.3).applies(ObjectKind::Smd, ObjectKind::Smd));
+        assert!(c(0.5).applies(ObjectKind::Smd, ObjectKind::Smd));
+        assert!(!c(0.5).applies(ObjectKind::Smd, ObjectKind::Via));
+    }
+
+    #[test]
+    fn diff_pairs() {
+        let dsn = TEST_DSN
+            .replace("(net B (pins R3-1 R4-1))", "(net B (pins R3-1 R4-1))\n    (pair (nets A B))");
+        let pcb = load_dsn(&dsn).unwrap();
+        let (a, b) = (pcb.to_id("A"), pcb.to_id("B"));
+        assert_eq!(pcb.diff_pairs(), [(a, b)]);
+        assert_eq!(pcb.diff_pair_partner(b), Some(a));
+        assert!(load_dsn(&dsn.replace("(nets A B)", "(nets A C)")).is_err());
+        assert!(load_dsn(&dsn.replace("(nets A B)", "(nets A)")).is_err());
+    }
+
+    // Loads |data| like |load_dsn|, but with quantization on.
+    fn load_quantized(data: &str) -> Pcb {
+        let (data, extras) = strip_extras(data).unwrap();
+        let dsn = Parser::new(&Lexer::new(&data).unwrap().lex().unwrap()).parse().unwrap();
+        let mut c = DesignToPcb::new(dsn);
+        c.set_extras(extras);
+        c.set_quantize(true);
+        c.convert().unwrap()
+    }
+
+    #[test]
+    fn quantize_dedupes_polygon_vertices() {
+        // The last vertex is the first one again, but off in the 12th decimal
+        // in mm. Widths aren't on the 0.1 um resolution, and aren't rounded.
+        let dsn = TEST_DSN
+            .replace(
+                "(via via600)",
+                "(via via600)
+    (keepout (polygon F.Cu 0 1000 1000 3000.04 1000 3000 3000 1000.000000001 1000))",
+            )
+            .replace("(rule (width 400))", "(rule (width 400.03))");
+        let pcb = load_quantized(&dsn);
+        let Shape::Polygon(p) = &pcb.keepouts()[0].shape.shape else { panic!("not a polygon") };
+        assert_eq!(p.pts().len(), 3, "{:?}", p.pts());
+        // Positions are rounded to the resolution.
+        assert!(p.pts().iter().any(|v| (v.x - 3.0).abs() < 1e-9), "{:?}", p.pts());
+        assert!(eq(pcb.net_ruleset(pcb.to_id("A")).radius().unwrap(), 0.200015));
+    }
+}
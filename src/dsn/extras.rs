@@ -0,0 +1,755 @@
+use std::ops::Range;
+
+use ahash::HashMap;
+use eyre::{eyre, Result};
+use memegeom::primitive::point::Pt;
+use memegeom::primitive::pt;
+
+// Parts of the DSN grammar which memedsn doesn't parse. |strip_extras| collects
+// these and removes them from the text handed to memedsn, so DesignToPcb can
+// convert them afterwards. Coordinates are in design units, as with memedsn.
+
+#[must_use]
+#[derive(Debug, Clone)]
+pub enum DsnExtraShape {
+    Rect { layer_id: String, l: f64, b: f64, r: f64, t: f64 },
+    Circle { layer_id: String, diameter: f64, p: Pt },
+    Polygon { layer_id: String, aperture_width: f64, pts: Vec<Pt> },
+    Path { layer_id: String, aperture_width: f64, pts: Vec<Pt> },
+}
+
+impl DsnExtraShape {
+    #[must_use]
+    pub fn layer_id(&self) -> &str {
+        match self {
+            DsnExtraShape::Rect { layer_id, .. }
+            | DsnExtraShape::Circle { layer_id, .. }
+            | DsnExtraShape::Polygon { layer_id, .. }
+            | DsnExtraShape::Path { layer_id, .. } => layer_id,
+        }
+    }
+}
+
+// Existing wire from the wiring section.
+#[must_use]
+#[derive(Debug, Clone)]
+pub struct DsnExtraWire {
+    pub shape: DsnExtraShape,
+    pub net_id: Option<String>,
+    pub fixed: bool, // Has type fix or protect, so it mustn't be rerouted.
+}
+
+// Existing vias from the wiring section, one at each of |pts|.
+#[must_use]
+#[derive(Debug, Clone)]
+pub struct DsnExtraVia {
+    pub padstack_id: String,
+    pub pts: Vec<Pt>,
+    pub net_id: Option<String>,
+    pub fixed: bool,
+}
+
+// Copper plane from the structure, with its windows (cutouts).
+#[must_use]
+#[derive(Debug, Clone)]
+pub struct DsnExtraPlane {
+    pub net_id: String,
+    pub shape: DsnExtraShape,
+    pub windows: Vec<DsnExtraShape>,
+}
+
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DsnExtraSupply {
+    Power,
+    Ground,
+}
+
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DsnExtraMirror {
+    X,
+    Y,
+    XY,
+}
+
+// Non-copper layer types. memedsn only knows copper layer types.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DsnExtraLayerType {
+    SolderMask,
+    Paste,
+    Silk,
+}
+
+// Layer descriptor clauses, keyed by layer name in |DsnExtras|.
+#[must_use]
+#[derive(Debug, Default, Clone)]
+pub struct DsnExtraLayer {
+    pub index: Option<usize>, // Position in the stackup, from (property (index ...)).
+    pub layer_type: Option<DsnExtraLayerType>,
+}
+
+// Net descriptor clauses, keyed by net id in |DsnExtras|.
+#[must_use]
+#[derive(Debug, Default, Clone)]
+pub struct DsnExtraNet {
+    pub fixed: bool, // Has type fix.
+    pub supply: Option<DsnExtraSupply>,
+    pub order: Vec<String>, // Pin references from (order ...), e.g. R1-1.
+}
+
+// Clearance types memedsn doesn't know. It only parses default_smd and smd_smd.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DsnExtraClearanceType {
+    DefaultSmd,
+    SmdSmd,
+    SmdViaSameNet,
+    ViaViaSameNet,
+    ViaAtSmd,
+    SmdToTurnGap,
+    PadToTurnGap,
+    AntipadGap,
+}
+
+#[must_use]
+#[derive(Debug, Clone)]
+pub struct DsnExtraClearance {
+    pub amount: f64,
+    pub types: Vec<DsnExtraClearanceType>,
+}
+
+// Class descriptor clauses, keyed by class id in |DsnExtras|.
+#[must_use]
+#[derive(Debug, Default, Clone)]
+pub struct DsnExtraClass {
+    pub use_layers: Vec<String>, // Layers from (circuit (use_layer ...)).
+    // Clearances from the class rules which have a type memedsn doesn't know.
+    pub clearances: Vec<DsnExtraClearance>,
+}
+
+#[must_use]
+#[derive(Debug, Default, Clone)]
+pub struct DsnExtras {
+    pub wires: Vec<DsnExtraWire>,
+    pub vias: Vec<DsnExtraVia>,
+    pub planes: Vec<DsnExtraPlane>,
+    // Windows of keepouts, keyed by the id of the image the keepout is in, or
+    // None for the structure, and the index of the keepout there.
+    pub keepout_windows: HashMap<(Option<String>, usize), Vec<DsnExtraShape>>,
+    pub layers: HashMap<String, DsnExtraLayer>,
+    pub nets: HashMap<String, DsnExtraNet>,
+    pub classes: HashMap<String, DsnExtraClass>,
+    pub pairs: Vec<(String, String)>, // Differential pairs of net ids.
+    pub mirrors: HashMap<String, DsnExtraMirror>, // Keyed by component id. Absent for off.
+    pub wire_grid: Option<f64>, // From (grid wire ...). Via and place grids aren't used.
+}
+
+impl DsnExtras {
+    // Whether the net has type fix, or has a wire or via which is fixed or
+    // protected. Routing around part of a net's existing wiring isn't supported,
+    // so it keeps all of it instead.
+    #[must_use]
+    pub fn is_net_fixed(&self, net_id: &str) -> bool {
+        let is_net = |v: &Option<String>| v.as_deref() == Some(net_id);
+        self.nets.get(net_id).is_some_and(|v| v.fixed)
+            || self.wires.iter().any(|v| v.fixed && is_net(&v.net_id))
+            || self.vias.iter().any(|v| v.fixed && is_net(&v.net_id))
+    }
+}
+
+// An atom or a list, with the range of the source text it came from.
+#[derive(Debug, Clone)]
+struct Node {
+    atom: Option<String>, // None for lists.
+    items: Vec<Node>,
+    span: Range<usize>,
+}
+
+impl Node {
+    fn atom(&self) -> Option<&str> {
+        self.atom.as_deref()
+    }
+
+    // Name of a list, i.e. its first item.
+    fn head(&self) -> Option<&str> {
+        self.items.first().and_then(Node::atom)
+    }
+
+    fn args(&self) -> &[Node] {
+        self.items.get(1..).unwrap_or_default()
+    }
+
+    fn is(&self, name: &str) -> bool {
+        self.head() == Some(name)
+    }
+
+    fn list(&self, name: &str) -> Option<&Node> {
+        self.items.iter().find(|v| v.is(name))
+    }
+
+    fn lists<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Node> {
+        self.items.iter().filter(move |v| v.is(name))
+    }
+
+    // First nested list, e.g. the shape of a (wire ...).
+    fn first_list(&self) -> Option<&Node> {
+        self.args().iter().find(|v| v.atom().is_none())
+    }
+
+    // Atom arguments, skipping any nested lists.
+    fn atoms(&self) -> impl Iterator<Item = &str> {
+        self.args().iter().filter_map(Node::atom)
+    }
+}
+
+// Parses the top level list of |data|. Quoted atoms only exist once the quote
+// character is set with (string_quote ...).
+fn parse(data: &str) -> Result<Node> {
+    let b = data.as_bytes();
+    let mut quote: Option<u8> = None;
+    let mut stack: Vec<(usize, Vec<Node>)> = Vec::new();
+    let mut i = 0;
+    while i < b.len() {
+        let start = i;
+        match b[i] {
+            c if c.is_ascii_whitespace() => i += 1,
+            b'(' => {
+                stack.push((start, Vec::new()));
+                i += 1;
+            }
+            b')' => {
+                let (start, items) = stack.pop().ok_or_else(|| eyre!("unmatched ) at {}", i))?;
+                i += 1;
+                let node = Node { atom: None, items, span: start..i };
+                match stack.last_mut() {
+                    Some((_, parent)) => parent.push(node),
+                    None => return Ok(node),
+                }
+            }
+            c => {
+                let Some((_, items)) = stack.last_mut() else {
+                    return Err(eyre!("atom outside of a list at {}", i));
+                };
+                let set_quote = items.len() == 1 && items[0].atom() == Some("string_quote");
+                let atom = if set_quote {
+                    // The quote character itself, which would otherwise start a string.
+                    i += 1;
+                    quote = Some(c);
+                    data[start..i].to_owned()
+                } else if quote == Some(c) {
+                    let len = b[i + 1..]
+                        .iter()
+                        .position(|&v| v == c)
+                        .ok_or_else(|| eyre!("unterminated string at {}", i))?;
+                    i += len + 2;
+                    data[start + 1..i - 1].to_owned()
+                } else {
+                    while i < b.len() && !b[i].is_ascii_whitespace() && !b"()".contains(&b[i]) {
+                        i += 1;
+                    }
+                    data[start..i].to_owned()
+                };
+                items.push(Node { atom: Some(atom), items: Vec::new(), span: start..i });
+            }
+        }
+    }
+    Err(eyre!("unterminated list"))
+}
+
+fn num(v: &str) -> Result<f64> {
+    v.parse().map_err(|_| eyre!("expected a number, got {}", v))
+}
+
+fn pts(v: &[f64]) -> Result<Vec<Pt>> {
+    let chunks = v.chunks_exact(2);
+    if !chunks.remainder().is_empty() {
+        return Err(eyre!("odd number of coordinates"));
+    }
+    Ok(chunks.map(|v| pt(v[0], v[1])).collect())
+}
+
+fn net_id(n: &Node) -> Option<String> {
+    n.list("net").and_then(|v| v.atoms().next()).map(str::to_owned)
+}
+
+// Value of a (type ...) clause.
+fn type_of(n: &Node) -> Option<&str> {
+    n.list("type").and_then(|v| v.atoms().next())
+}
+
+fn is_fixed_wiring(n: &Node) -> bool {
+    matches!(type_of(n), Some("fix" | "protect"))
+}
+
+// Parses a clearance with a type memedsn doesn't know, or returns None if it
+// only has types memedsn knows.
+fn extra_clearance(n: &Node) -> Result<Option<DsnExtraClearance>> {
+    let Some(t) = n.list("type") else { return Ok(None) };
+    let types = t
+        .atoms()
+        .map(|v| {
+            Ok(match v {
+                "default_smd" => DsnExtraClearanceType::DefaultSmd,
+                "smd_smd" => DsnExtraClearanceType::SmdSmd,
+                "smd_via_same_net" => DsnExtraClearanceType::SmdViaSameNet,
+                "via_via_same_net" => DsnExtraClearanceType::ViaViaSameNet,
+                "via_at_smd" => DsnExtraClearanceType::ViaAtSmd,
+                "smd_to_turn_gap" => DsnExtraClearanceType::SmdToTurnGap,
+                "pad_to_turn_gap" => DsnExtraClearanceType::PadToTurnGap,
+                "antipad_gap" => DsnExtraClearanceType::AntipadGap,
+                _ => return Err(eyre!("unknown clearance type {}", v)),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let known = [DsnExtraClearanceType::DefaultSmd, DsnExtraClearanceType::SmdSmd];
+    if types.iter().all(|v| known.contains(v)) {
+        return Ok(None);
+    }
+    let amount = n.atoms().next().ok_or_else(|| eyre!("clearance without an amount"))?;
+    Ok(Some(DsnExtraClearance { amount: num(amount)?, types }))
+}
+
+fn shape(n: &Node) -> Result<DsnExtraShape> {
+    let mut atoms = n.atoms();
+    let layer_id = atoms.next().ok_or_else(|| eyre!("shape without a layer"))?.to_owned();
+    let v = atoms.map(num).collect::<Result<Vec<_>>>()?;
+    Ok(match (n.head(), &v[..]) {
+        (Some("rect"), &[x0, y0, x1, y1]) => DsnExtraShape::Rect {
+            layer_id,
+            l: x0.min(x1),
+            b: y0.min(y1),
+            r: x0.max(x1),
+            t: y0.max(y1),
+        },
+        (Some("circle"), &[diameter]) => {
+            DsnExtraShape::Circle { layer_id, diameter, p: Pt::zero() }
+        }
+        (Some("circle"), &[diameter, x, y]) => {
+            DsnExtraShape::Circle { layer_id, diameter, p: pt(x, y) }
+        }
+        (Some("polygon"), &[aperture_width, ref rest @ ..]) => {
+            DsnExtraShape::Polygon { layer_id, aperture_width, pts: pts(rest)? }
+        }
+        (Some("path"), &[aperture_width, ref rest @ ..]) => {
+            DsnExtraShape::Path { layer_id, aperture_width, pts: pts(rest)? }
+        }
+        _ => return Err(eyre!("unsupported shape {}", n.head().unwrap_or_default())),
+    })
+}
+
+// Shapes of the (window ...) clauses of |n|.
+fn windows(n: &Node) -> Result<Vec<DsnExtraShape>> {
+    n.lists("window")
+        .map(|w| shape(w.first_list().ok_or_else(|| eyre!("window without a shape"))?))
+        .collect()
+}
+
+#[derive(Debug, Default)]
+struct Stripper {
+    extras: DsnExtras,
+    edits: Vec<(Range<usize>, String)>, // Replacements for ranges of the source text.
+}
+
+impl Stripper {
+    fn strip(&mut self, n: &Node) {
+        self.replace(n, "");
+    }
+
+    fn replace(&mut self, n: &Node, s: &str) {
+        self.edits.push((n.span.clone(), s.to_owned()));
+    }
+
+    fn wiring(&mut self, n: &Node) -> Result<()> {
+        for v in n.lists("wire") {
+            let s = v.first_list().ok_or_else(|| eyre!("wire without a shape"))?;
+            self.extras.wires.push(DsnExtraWire {
+                shape: shape(s)?,
+                net_id: net_id(v),
+                fixed: is_fixed_wiring(v),
+            });
+            self.strip(v);
+        }
+        for v in n.lists("via") {
+            let mut atoms = v.atoms();
+            let padstack_id = atoms.next().ok_or_else(|| eyre!("via without a padstack"))?;
+            let pts = pts(&atoms.map(num).collect::<Result<Vec<_>>>()?)?;
+            self.extras.vias.push(DsnExtraVia {
+                padstack_id: padstack_id.to_owned(),
+                pts,
+                net_id: net_id(v),
+                fixed: is_fixed_wiring(v),
+            });
+            self.strip(v);
+        }
+        Ok(())
+    }
+
+    // Keepouts in memedsn are in the order they appear in the structure or image.
+    fn keepouts(&mut self, n: &Node, image_id: Option<&str>) -> Result<()> {
+        let keepouts = n
+            .items
+            .iter()
+            .filter(|v| matches!(v.head(), Some("keepout" | "via_keepout" | "wire_keepout")));
+        for (idx, v) in keepouts.enumerate() {
+            let windows = windows(v)?;
+            for w in v.lists("window") {
+                self.strip(w);
+            }
+            if !windows.is_empty() {
+                self.extras.keepout_windows.insert((image_id.map(str::to_owned), idx), windows);
+            }
+        }
+        Ok(())
+    }
+
+    fn planes(&mut self, n: &Node) -> Result<()> {
+        for v in n.lists("plane") {
+            let net_id = v.atoms().next().ok_or_else(|| eyre!("plane without a net"))?;
+            let s = v.first_list().ok_or_else(|| eyre!("plane without a shape"))?;
+            self.extras.planes.push(DsnExtraPlane {
+                net_id: net_id.to_owned(),
+                shape: shape(s)?,
+                windows: windows(v)?,
+            });
+            self.strip(v);
+        }
+        Ok(())
+    }
+
+    fn grids(&mut self, n: &Node) -> Result<()> {
+        for v in n.lists("grid") {
+            let mut atoms = v.atoms();
+            let (Some(kind), Some(dim)) = (atoms.next(), atoms.next()) else {
+                return Err(eyre!("grid without a type and dimension"));
+            };
+            if kind == "wire" {
+                self.extras.wire_grid = Some(num(dim)?);
+            }
+            self.strip(v);
+        }
+        Ok(())
+    }
+
+    fn layers(&mut self, n: &Node) -> Result<()> {
+        for v in n.lists("layer") {
+            let name = v.atoms().next().ok_or_else(|| eyre!("layer without a name"))?;
+            let layer_type = match type_of(v) {
+                Some("mask") => Some(DsnExtraLayerType::SolderMask),
+                Some("paste") => Some(DsnExtraLayerType::Paste),
+                Some("silk") => Some(DsnExtraLayerType::Silk),
+                _ => None,
+            };
+            let mut layer = DsnExtraLayer { index: None, layer_type };
+            if let (Some(t), Some(_)) = (v.list("type"), layer_type) {
+                // Keep a type memedsn accepts. DesignToPcb uses |layer_type| instead.
+                self.replace(t, "(type signal)");
+            }
+            if let Some(p) = v.list("property") {
+                if let Some(index) = p.list("index").and_then(|v| v.atoms().next()) {
+                    layer.index = Some(
+                        index.parse().map_err(|_| eyre!("invalid index for layer {}", name))?,
+                    );
+                }
+                self.strip(p);
+            }
+            self.extras.layers.insert(name.to_owned(), layer);
+        }
+        Ok(())
+    }
+
+    fn network(&mut self, n: &Node) -> Result<()> {
+        for v in n.lists("net") {
+            let net_id = v.atoms().next().ok_or_else(|| eyre!("net without an id"))?;
+            let mut net = DsnExtraNet::default();
+            if let Some(t) = v.list("type") {
+                net.fixed = type_of(v) == Some("fix");
+                self.strip(t);
+            }
+            if let Some(supply) = v.list("supply") {
+                net.supply = Some(match supply.atoms().next() {
+                    Some("power") => DsnExtraSupply::Power,
+                    Some("ground") => DsnExtraSupply::Ground,
+                    _ => return Err(eyre!("unknown supply for net {}", net_id)),
+                });
+                self.strip(supply);
+            }
+            for o in v.lists("order") {
+                net.order.extend(o.atoms().map(str::to_owned));
+                self.strip(o);
+            }
+            self.extras.nets.insert(net_id.to_owned(), net);
+        }
+        for v in n.lists("pair") {
+            let nets: Vec<&str> = v.list("nets").map(|v| v.atoms().collect()).unwrap_or_default();
+            let &[a, b] = &nets[..] else {
+                return Err(eyre!("pair must have two nets, got {}", nets.len()));
+            };
+            self.extras.pairs.push((a.to_owned(), b.to_owned()));
+            self.strip(v);
+        }
+        for v in n.lists("class") {
+            let class_id = v.atoms().next().ok_or_else(|| eyre!("class without an id"))?;
+            let mut class = DsnExtraClass::default();
+            for c in v.lists("circuit") {
+                class.use_layers.extend(
+                    c.lists("use_layer").flat_map(|v| v.atoms().map(str::to_owned)),
+                );
+                if c.args().iter().all(|v| v.is("use_layer")) {
+                    self.strip(c);
+                } else {
+                    for u in c.lists("use_layer") {
+                        self.strip(u);
+                    }
+                }
+            }
+            for r in v.lists("rule") {
+                let mut extra = Vec::new();
+                for c in r.lists("clearance") {
+                    if let Some(clearance) = extra_clearance(c)? {
+                        class.clearances.push(clearance);
+                        extra.push(c);
+                    }
+                }
+                if !extra.is_empty() && extra.len() == r.args().len() {
+                    self.strip(r);
+                } else {
+                    for c in extra {
+                        self.strip(c);
+                    }
+                }
+            }
+            self.extras.classes.insert(class_id.to_owned(), class);
+        }
+        Ok(())
+    }
+
+    fn placement(&mut self, n: &Node) -> Result<()> {
+        for v in n.lists("component").flat_map(|v| v.lists("place")) {
+            let Some(m) = v.list("mirror") else { continue };
+            let component_id = v.atoms().next().ok_or_else(|| eyre!("place without an id"))?;
+            let mirror = match m.atoms().next() {
+                Some("x") => Some(DsnExtraMirror::X),
+                Some("y") => Some(DsnExtraMirror::Y),
+                Some("xy") => Some(DsnExtraMirror::XY),
+                Some("off") => None,
+                _ => return Err(eyre!("unknown mirror for component {}", component_id)),
+            };
+            if let Some(mirror) = mirror {
+                self.extras.mirrors.insert(component_id.to_owned(), mirror);
+            }
+            self.strip(m);
+        }
+        Ok(())
+    }
+
+    fn apply(mut self, data: &str) -> (String, DsnExtras) {
+        self.edits.sort_by_key(|(r, _)| r.start);
+        let mut out = String::with_capacity(data.len());
+        let mut at = 0;
+        for (r, s) in &self.edits {
+            out.push_str(&data[at..r.start]);
+            out.push_str(s);
+            at = r.end;
+        }
+        out.push_str(&data[at..]);
+        (out, self.extras)
+    }
+}
+
+// Collects the parts of |data| which memedsn doesn't parse, and returns the
+// text without them along with what was collected.
+pub fn strip_extras(data: &str) -> Result<(String, DsnExtras)> {
+    let pcb = parse(data)?;
+    let mut s = Stripper::default();
+    for v in pcb.lists("structure") {
+        s.layers(v)?;
+        s.grids(v)?;
+        s.planes(v)?;
+        s.keepouts(v, None)?;
+    }
+    for v in pcb.lists("placement") {
+        s.placement(v)?;
+    }
+    for v in pcb.lists("library").flat_map(|v| v.lists("image")) {
+        let image_id = v.atoms().next().ok_or_else(|| eyre!("image without an id"))?;
+        s.keepouts(v, Some(image_id))?;
+    }
+    for v in pcb.lists("network") {
+        s.network(v)?;
+    }
+    for v in pcb.lists("wiring") {
+        s.wiring(v)?;
+    }
+    Ok(s.apply(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use memegeom::geom::math::eq;
+
+    use super::*;
+
+    #[test]
+    fn quoted_atoms() {
+        let n = parse("(pcb (parser (string_quote \")) (net \"a (b)\" c))").unwrap();
+        let net = n.list("net").unwrap();
+        assert_eq!(net.atoms().collect::<Vec<_>>(), ["a (b)", "c"]);
+        let quote = n.list("parser").unwrap().list("string_quote").unwrap();
+        assert_eq!(quote.atoms().collect::<Vec<_>>(), ["\""]);
+    }
+
+    #[test]
+    fn strip_wiring() {
+        let data = "(pcb (wiring (wire (path F.Cu 200 0 0 10 0) (net A)) (via v 1 2 (net A))))";
+        let (text, extras) = strip_extras(data).unwrap();
+        assert_eq!(text, "(pcb (wiring  ))");
+        assert_eq!(extras.wires.len(), 1);
+        assert_eq!(extras.wires[0].net_id.as_deref(), Some("A"));
+        assert_eq!(extras.wires[0].shape.layer_id(), "F.Cu");
+        assert_eq!(extras.vias.len(), 1);
+        assert_eq!(extras.vias[0].padstack_id, "v");
+        assert_eq!(extras.vias[0].pts.len(), 1);
+        assert!(!extras.is_net_fixed("A"));
+    }
+
+    #[test]
+    fn strip_fixed() {
+        let data = "(pcb (network (net A (type fix) (pins R1-1)) (net B (pins R2-1)) (net C))
+          (wiring (wire (path F.Cu 200 0 0 10 0) (net B) (type protect))))";
+        let (text, extras) = strip_extras(data).unwrap();
+        assert!(!text.contains("type"), "{text}");
+        assert!(extras.is_net_fixed("A"));
+        assert!(extras.is_net_fixed("B"));
+        assert!(!extras.is_net_fixed("C"));
+    }
+
+    #[test]
+    fn strip_supply() {
+        let data = "(pcb (network (net GND (supply ground) (pins R1-1)) (net B (pins R2-1))))";
+        let (text, extras) = strip_extras(data).unwrap();
+        assert!(!text.contains("supply"), "{text}");
+        assert_eq!(extras.nets["GND"].supply, Some(DsnExtraSupply::Ground));
+        assert_eq!(extras.nets["B"].supply, None);
+        assert!(strip_extras("(pcb (network (net A (supply vcc))))").is_err());
+    }
+
+    #[test]
+    fn strip_order() {
+        let data = "(pcb (network (net A (pins R1-1 R2-1 R3-1) (order R3-1 R1-1))))";
+        let (text, extras) = strip_extras(data).unwrap();
+        assert_eq!(text, "(pcb (network (net A (pins R1-1 R2-1 R3-1) )))");
+        assert_eq!(extras.nets["A"].order, ["R3-1", "R1-1"]);
+    }
+
+    #[test]
+    fn strip_grid() {
+        let data = "(pcb (structure (grid via 500) (grid wire 250 (direction x))))";
+        let (text, extras) = strip_extras(data).unwrap();
+        assert_eq!(text, "(pcb (structure  ))");
+        assert_eq!(extras.wire_grid, Some(250.0));
+        assert!(strip_extras("(pcb (structure (grid wire)))").is_err());
+    }
+
+    #[test]
+    fn strip_pairs() {
+        let data = "(pcb (network (net P (pins R1-1)) (net N (pins R2-1)) (pair (nets P N))))";
+        let (text, extras) = strip_extras(data).unwrap();
+        assert_eq!(text, "(pcb (network (net P (pins R1-1)) (net N (pins R2-1)) ))");
+        assert_eq!(extras.pairs, [("P".to_owned(), "N".to_owned())]);
+        assert!(strip_extras("(pcb (network (pair (nets P))))").is_err());
+    }
+
+    #[test]
+    fn strip_layer_index() {
+        let data = "(pcb (structure (layer F.Cu (type signal) (property (index 1))) (layer B.Cu)))";
+        let (text, extras) = strip_extras(data).unwrap();
+        assert_eq!(text, "(pcb (structure (layer F.Cu (type signal) ) (layer B.Cu)))");
+        assert_eq!(extras.layers["F.Cu"].index, Some(1));
+        assert_eq!(extras.layers["B.Cu"].index, None);
+    }
+
+    #[test]
+    fn strip_layer_type() {
+        let data = "(pcb (structure (layer F.Cu (type signal)) (layer F.Mask (type mask))))";
+        let (text, extras) = strip_extras(data).unwrap();
+        assert_eq!(text, data.replace("mask", "signal"));
+        assert_eq!(extras.layers["F.Cu"].layer_type, None);
+        assert_eq!(extras.layers["F.Mask"].layer_type, Some(DsnExtraLayerType::SolderMask));
+    }
+
+    #[test]
+    fn strip_use_layer() {
+        let data = "(pcb (network
+          (class a A (circuit (use_via v) (use_layer F.Cu In1.Cu)))
+          (class b B (circuit (use_layer B.Cu)) (rule (width 1)))))";
+        let (text, extras) = strip_extras(data).unwrap();
+        assert!(!text.contains("use_layer"), "{text}");
+        assert!(text.contains("(class a A (circuit (use_via v) ))"), "{text}");
+        assert!(text.contains("(class b B  (rule (width 1)))"), "{text}");
+        assert_eq!(extras.classes["a"].use_layers, ["F.Cu", "In1.Cu"]);
+        assert_eq!(extras.classes["b"].use_layers, ["B.Cu"]);
+    }
+
+    #[test]
+    fn strip_mirror() {
+        let data = "(pcb (placement (component I
+          (place R1 0 0 front 0 (mirror xy)) (place R2 0 0 back 0 (mirror off)) (place R3 0 0))))";
+        let (text, extras) = strip_extras(data).unwrap();
+        assert!(!text.contains("mirror"), "{text}");
+        assert_eq!(extras.mirrors.get("R1"), Some(&DsnExtraMirror::XY));
+        assert_eq!(extras.mirrors.get("R2"), None);
+        assert_eq!(extras.mirrors.get("R3"), None);
+    }
+
+    #[test]
+    fn strip_planes() {
+        let data = "(pcb (structure (layer B.Cu)
+          (plane GND (rect B.Cu 0 0 10 10)
+            (window (circle B.Cu 2 5 5)) (window (rect B.Cu 0 0 1 1)))))";
+        let (text, extras) = strip_extras(data).unwrap();
+        assert!(!text.contains("plane"), "{text}");
+        assert_eq!(extras.planes.len(), 1);
+        assert_eq!(extras.planes[0].net_id, "GND");
+        assert_eq!(extras.planes[0].shape.layer_id(), "B.Cu");
+        assert_eq!(extras.planes[0].windows.len(), 2);
+    }
+
+    #[test]
+    fn strip_clearance_types() {
+        let data = "(pcb (network
+          (class a A (rule (width 1) (clearance 2 (type smd_via_same_net)) (clearance 3)))
+          (class b B (rule (clearance 4 (type antipad_gap pad_to_turn_gap))))
+          (class c C (rule (clearance 5 (type smd_smd))))))";
+        let (text, extras) = strip_extras(data).unwrap();
+        assert!(text.contains("(class a A (rule (width 1)  (clearance 3)))"), "{text}");
+        assert!(text.contains("(class b B )"), "{text}");
+        assert!(text.contains("(clearance 5 (type smd_smd))"), "{text}");
+        let a = &extras.classes["a"].clearances;
+        assert_eq!(a.len(), 1);
+        assert!(eq(a[0].amount, 2.0));
+        assert_eq!(a[0].types, [DsnExtraClearanceType::SmdViaSameNet]);
+        let b = &extras.classes["b"].clearances;
+        assert_eq!(
+            b[0].types,
+            [DsnExtraClearanceType::AntipadGap, DsnExtraClearanceType::PadToTurnGap]
+        );
+        assert!(extras.classes["c"].clearances.is_empty());
+        assert!(strip_extras("(pcb (network (class a (rule (clearance 1 (type x))))))").is_err());
+    }
+
+    #[test]
+    fn strip_keepout_windows() {
+        let data = "(pcb
+          (structure
+            (keepout (rect F.Cu 0 0 4 4))
+            (wire_keepout (rect F.Cu 0 0 4 4) (window (rect F.Cu 1 1 3 3))))
+          (library (image I (keepout (circle F.Cu 4) (window (circle F.Cu 2))))))";
+        let (text, extras) = strip_extras(data).unwrap();
+        assert!(!text.contains("window"), "{text}");
+        assert_eq!(extras.keepout_windows.len(), 2);
+        assert_eq!(extras.keepout_windows[&(None, 1)].len(), 1);
+        assert_eq!(extras.keepout_windows[&(Some("I".to_owned()), 0)].len(), 1);
+    }
+}
@@ -1,2 +1,26 @@
 pub mod design_to_pcb;
+pub mod extras;
+pub mod pcb_to_netlist;
 pub mod pcb_to_session;
+
+use memedsn::lexer::Lexer;
+use memedsn::parser::Parser;
+
+use crate::dsn::design_to_pcb::DesignToPcb;
+use crate::dsn::extras::strip_extras;
+use crate::error::MemerouteError;
+use crate::model::pcb::Pcb;
+
+// Parses and converts the contents of a DSN file.
+pub fn load_dsn(data: &str) -> Result<Pcb, MemerouteError> {
+    let parse = || -> eyre::Result<_> {
+        // memedsn doesn't parse everything, so it gets the text without the rest.
+        let (data, extras) = strip_extras(data)?;
+        Ok((Parser::new(&Lexer::new(&data)?.lex()?).parse()?, extras))
+    };
+    let (dsn, extras) =
+        parse().map_err(|e| MemerouteError::from_report(e, MemerouteError::ParseError))?;
+    let mut c = DesignToPcb::new(dsn);
+    c.set_extras(extras);
+    c.convert()
+}
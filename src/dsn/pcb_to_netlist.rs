@@ -0,0 +1,37 @@
+use eyre::Result;
+
+use crate::kicad::pcb_to_kicad::PcbToKicad;
+use crate::model::pcb::Pcb;
+
+// Writes the connectivity of a PCB as a KiCad style netlist. Nets are sorted
+// by name and pins by component then pin, so the output is deterministic.
+#[must_use]
+#[derive(Debug, Clone)]
+pub struct PcbToNetlist {
+    pcb: Pcb,
+}
+
+impl PcbToNetlist {
+    pub fn new(pcb: Pcb) -> Self {
+        Self { pcb }
+    }
+
+    pub fn convert(self) -> Result<String> {
+        PcbToKicad::new(self.pcb).netlist()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_pcb;
+
+    #[test]
+    fn netlist_lists_net_pins() {
+        let s = PcbToNetlist::new(test_pcb()).convert().unwrap();
+        let net = "(net (code \"1\") (name \"A\")\n      (node (ref \"R1\") (pin \"1\"))\n      \
+                   (node (ref \"R2\") (pin \"1\")))";
+        assert!(s.contains(net), "{s}");
+        assert_eq!(s, PcbToKicad::new(test_pcb()).netlist().unwrap());
+    }
+}
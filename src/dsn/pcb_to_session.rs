@@ -1,7 +1,8 @@
+use std::io::Write;
+
 use ahash::HashMap;
 use eyre::{eyre, Result};
 use memegeom::primitive::circle::Circle;
-use memegeom::primitive::path_shape::Path;
 use memegeom::primitive::point::Pt;
 use memegeom::primitive::polygon::Poly;
 use memegeom::primitive::rect::Rt;
@@ -9,7 +10,8 @@ use memegeom::primitive::shape::Shape;
 use strum::IntoEnumIterator;
 
 use crate::model::pcb::{
-    Component, LayerKind, LayerSet, LayerShape, Net, Padstack, Pcb, Via, Wire,
+    Component, Keepout, KeepoutType, LayerKind, LayerSet, LayerShape, Net, Padstack, Pcb, Plane,
+    Via, Wire,
 };
 use crate::name::Id;
 
@@ -23,13 +25,34 @@ const MM_RESOLUTION: usize = 100000;
 pub struct PcbToSession {
     pcb: Pcb,
     s: String,
-    indent: usize, // Current indent.
-    col: usize,    // Current column number.
+    indent: usize,                  // Current indent.
+    col: usize,                     // Current column number.
+    host: Option<(String, String)>, // Host CAD name and version to record, if any.
+    resolution: usize,              // Output units per mm.
 }
 
 impl PcbToSession {
     pub fn new(pcb: Pcb) -> Self {
-        Self { pcb, s: String::new(), indent: 0, col: 0 }
+        Self { pcb, s: String::new(), indent: 0, col: 0, host: None, resolution: MM_RESOLUTION }
+    }
+
+    // Sets the number of decimal places in mm that coordinates are output with.
+    // Coordinates are rounded to this precision.
+    pub fn set_precision(&mut self, digits: u32) -> Result<()> {
+        self.resolution =
+            10usize.checked_pow(digits).ok_or_else(|| eyre!("precision too large: {digits}"))?;
+        Ok(())
+    }
+
+    // Records |cad| and |version| as the generating tool in the parser section
+    // of the routes.
+    pub fn set_host(&mut self, cad: &str, version: &str) {
+        self.host = Some((cad.to_owned(), version.to_owned()));
+    }
+
+    // Records memeroute itself as the generating tool.
+    pub fn set_host_memeroute(&mut self) {
+        self.set_host("memeroute", env!("CARGO_PKG_VERSION"));
     }
 
     fn newline(&mut self) {
@@ -60,7 +83,7 @@ impl PcbToSession {
     }
 
     fn coord(&mut self, v: f64) {
-        let v = (v * MM_RESOLUTION as f64).round() as i64;
+        let v = (v * self.resolution as f64).round() as i64;
         self.token(&v.to_string());
     }
 
@@ -92,7 +115,7 @@ impl PcbToSession {
     fn resolution(&mut self) {
         self.begin("resolution");
         self.token("mm");
-        self.token(&MM_RESOLUTION.to_string());
+        self.token(&self.resolution.to_string());
         self.end();
     }
 
@@ -123,11 +146,11 @@ impl PcbToSession {
         self.end();
     }
 
-    fn path(&mut self, layer: &str, s: &Path) {
+    fn path(&mut self, layer: &str, pts: &[Pt], r: f64) {
         self.begin("path");
         self.name(layer);
-        self.coord(s.r() * 2.0);
-        for pt in s.pts() {
+        self.coord(r * 2.0);
+        for pt in pts {
             self.pt(*pt);
         }
         self.end();
@@ -160,41 +183,51 @@ impl PcbToSession {
         } else {
             // Otherwise, search for a LayerKind that gives this set.
             for kind in LayerKind::iter() {
+                let name = match kind {
+                    LayerKind::All => "all",
+                    LayerKind::Signal => "signal",
+                    LayerKind::Power => "power",
+                    LayerKind::Mixed => "mixed",
+                    LayerKind::Jumper => "jumper",
+                    // Specctra has no names for non-copper layer kinds.
+                    LayerKind::SolderMask | LayerKind::Paste | LayerKind::Silk => continue,
+                };
                 if l == self.pcb.layers_by_kind(kind) {
-                    return Some(
-                        match kind {
-                            LayerKind::All => "all",
-                            LayerKind::Signal => "signal",
-                            LayerKind::Power => "power",
-                            LayerKind::Mixed => "mixed",
-                            LayerKind::Jumper => "jumper",
-                        }
-                        .to_string(),
-                    );
+                    return Some(name.to_string());
                 }
             }
             None
         }
     }
 
-    fn shape(&mut self, shape: &LayerShape) {
-        let l = self.layer_id(shape.layers).unwrap();
+    fn shape(&mut self, shape: &LayerShape) -> Result<()> {
+        let l = self
+            .layer_id(shape.layers)
+            .ok_or_else(|| eyre!("no layer name for layer set {:?}", shape.layers))?;
         match &shape.shape {
+            Shape::Capsule(s) => self.path(&l, &[s.st(), s.en()], s.r()),
             Shape::Circle(s) => self.circle(&l, s),
-            Shape::Path(s) => self.path(&l, s),
+            Shape::Compound(s) => {
+                for v in s.quadtree().shapes() {
+                    self.shape(&LayerShape { layers: shape.layers, shape: v.shape().clone() })?;
+                }
+            }
+            Shape::Path(s) => self.path(&l, s.pts(), s.r()),
             Shape::Polygon(s) => self.polygon(&l, s),
             Shape::Rect(s) => self.rect(&l, s),
-            _ => unimplemented!(), // TODO: Transform these shapes.
+            Shape::Segment(s) => self.path(&l, &[s.st(), s.en()], 0.0), // No width, but see |wire|.
+            s => return Err(eyre!("unsupported shape in session output: {s:?}")),
         }
+        Ok(())
     }
 
-    fn padstack(&mut self, ps: &Padstack) {
+    fn padstack(&mut self, ps: &Padstack) -> Result<()> {
         self.begin("padstack");
         self.id(ps.id);
 
         for shape in &ps.shapes {
             self.begin("shape");
-            self.shape(shape);
+            self.shape(shape)?;
             self.end();
         }
 
@@ -205,12 +238,53 @@ impl PcbToSession {
         }
 
         self.end();
+        Ok(())
+    }
+
+    fn keepout(&mut self, k: &Keepout) -> Result<()> {
+        self.begin(match k.kind {
+            KeepoutType::Keepout => "keepout",
+            KeepoutType::ViaKeepout => "via_keepout",
+            KeepoutType::WireKeepout => "wire_keepout",
+        });
+        self.shape(&k.shape)?;
+        for w in &k.windows {
+            self.begin("window");
+            self.shape(&LayerShape { layers: k.shape.layers, shape: w.clone() })?;
+            self.end();
+        }
+        self.end();
+        Ok(())
+    }
+
+    // Planes are written as wires with windows, since the session has no plane clause.
+    fn plane(&mut self, p: &Plane) -> Result<()> {
+        self.begin("wire");
+        self.shape(&p.shape)?;
+        for w in &p.windows {
+            self.begin("window");
+            self.shape(&LayerShape { layers: p.shape.layers, shape: w.clone() })?;
+            self.end();
+        }
+        self.end();
+        Ok(())
     }
 
-    fn wire(&mut self, w: &Wire) {
+    fn wire(&mut self, w: &Wire) -> Result<()> {
         self.begin("wire");
-        self.shape(&w.shape);
+        if let Shape::Segment(s) = &w.shape.shape {
+            // Segments have no width, so use the width of the net's wires.
+            let l = self
+                .layer_id(w.shape.layers)
+                .ok_or_else(|| eyre!("no layer name for layer set {:?}", w.shape.layers))?;
+            let layer = w.shape.layers.iter().next().ok_or_else(|| eyre!("wire has no layers"))?;
+            let r = self.pcb.net_radius(w.net_id, layer)?;
+            self.path(&l, &[s.st(), s.en()], r);
+        } else {
+            self.shape(&w.shape)?;
+        }
         self.end();
+        Ok(())
     }
 
     fn via(&mut self, v: &Via) {
@@ -223,19 +297,36 @@ impl PcbToSession {
         self.end();
     }
 
-    fn net(&mut self, net: &Net, wires: &[Wire], vias: &[Via]) {
+    fn net(&mut self, net: &Net, wires: &[Wire], vias: &[Via], planes: &[Plane]) -> Result<()> {
         self.begin("net");
         self.id(net.id);
         for wire in wires {
-            self.wire(wire);
+            self.wire(wire)?;
+        }
+        for plane in planes {
+            self.plane(plane)?;
         }
         for via in vias {
             self.via(via);
         }
         self.end();
+        Ok(())
+    }
+
+    // Writes out buffered output, so memory use is bounded by the largest item.
+    fn flush<W: Write>(&mut self, w: &mut W) -> Result<()> {
+        w.write_all(self.s.as_bytes())?;
+        self.s.clear();
+        Ok(())
     }
 
-    pub fn convert(mut self) -> Result<String> {
+    pub fn convert(self) -> Result<String> {
+        let mut buf = Vec::new();
+        self.write(&mut buf)?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    pub fn write<W: Write>(mut self, w: &mut W) -> Result<()> {
         let pcb = self.pcb.clone();
 
         self.begin("session");
@@ -253,8 +344,13 @@ impl PcbToSession {
         for c in pcb.components() {
             footprints.entry(pcb.to_name(c.footprint_id)).or_insert_with(Vec::new).push(c.clone());
         }
-        for (name, cs) in footprints {
+        // Sort so the output doesn't depend on hash order.
+        let mut footprints: Vec<_> = footprints.into_iter().collect();
+        footprints.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        for (name, mut cs) in footprints {
+            cs.sort_unstable_by_key(|c| c.id);
             self.component(&name, cs);
+            self.flush(w)?;
         }
 
         self.end();
@@ -262,17 +358,38 @@ impl PcbToSession {
         self.begin("routes");
         self.resolution();
 
+        if let Some((cad, version)) = self.host.clone() {
+            self.begin("parser");
+            self.begin("host_cad");
+            self.name(&cad);
+            self.end();
+            self.begin("host_version");
+            self.name(&version);
+            self.end();
+            self.end();
+        }
+
+        // Output keepouts so round trips don't lose them.
+        if !pcb.keepouts().is_empty() {
+            self.begin("structure_out");
+            for k in pcb.keepouts() {
+                self.keepout(k)?;
+            }
+            self.end();
+        }
+
         self.begin("library_out");
         // Output vias used
-        for ps in pcb.via_padstacks() {
-            self.padstack(ps);
+        let used = pcb.used_padstacks();
+        for ps in pcb.via_padstacks().iter().filter(|p| used.contains(&p.id)) {
+            self.padstack(ps)?;
         }
         self.end();
 
         self.begin("network_out");
-        let mut nets: HashMap<Id, (Net, Vec<Wire>, Vec<Via>)> = HashMap::default();
+        let mut nets: HashMap<Id, (Net, Vec<Wire>, Vec<Via>, Vec<Plane>)> = HashMap::default();
         for net in pcb.nets() {
-            nets.insert(net.id, (net.clone(), Vec::new(), Vec::new()));
+            nets.insert(net.id, (net.clone(), Vec::new(), Vec::new(), Vec::new()));
         }
         for wire in pcb.wires() {
             nets.get_mut(&wire.net_id)
@@ -286,15 +403,187 @@ impl PcbToSession {
                 .2
                 .push(via.clone());
         }
+        for plane in pcb.planes() {
+            nets.get_mut(&plane.net_id)
+                .ok_or_else(|| eyre!("missing net with name {}", pcb.to_name(plane.net_id)))?
+                .3
+                .push(plane.clone());
+        }
 
-        for (net, wires, vias) in nets.values() {
-            self.net(net, wires, vias);
+        let mut nets: Vec<_> = nets.into_values().collect();
+        nets.sort_unstable_by_key(|v| v.0.id);
+        for (net, wires, vias, planes) in &nets {
+            self.net(net, wires, vias, planes)?;
+            self.flush(w)?;
         }
         self.end();
 
         self.end();
 
         self.end();
-        Ok(self.s)
+        self.flush(w)
+    }
+}
+
+// Streams a session file for |pcb| to |w|.
+pub fn write_session<W: Write>(pcb: Pcb, w: &mut W) -> Result<()> {
+    PcbToSession::new(pcb).write(w)
+}
+
+#[cfg(test)]
+mod tests {
+    use memegeom::geom::math::eq;
+    use memegeom::primitive::{cap, circ, pt, rt, seg, ShapeOps};
+
+    use super::*;
+    use crate::dsn::load_dsn;
+    use crate::testing::{test_pcb, TEST_DSN};
+
+    fn writer() -> PcbToSession {
+        let mut pcb = test_pcb();
+        let net_id = pcb.to_id("A");
+        let via = pcb.via_padstacks()[0].clone();
+        pcb.add_via_padstack(Padstack { id: pcb.to_id("unused_via"), ..via.clone() });
+        pcb.add_via(Via { p: pt(10.0, 10.0), padstack: via, net_id });
+        let layers = pcb.copper_layers();
+        let shape = LayerShape { layers, shape: rt(1.0, 1.0, 2.0, 2.0).shape() };
+        pcb.add_keepout(Keepout { kind: KeepoutType::Keepout, shape, windows: Vec::new() });
+        let mut w = PcbToSession::new(pcb);
+        w.set_host("memeroute", "1.0");
+        w
+    }
+
+    fn session() -> String {
+        writer().convert().unwrap()
+    }
+
+    fn pos(s: &str, pat: &str) -> usize {
+        s.find(pat).unwrap_or_else(|| panic!("missing {pat} in {s}"))
+    }
+
+    #[test]
+    fn write_matches_convert() {
+        let mut buf = Vec::new();
+        writer().write(&mut buf).unwrap();
+        assert_eq!(buf, session().into_bytes());
+    }
+
+    #[test]
+    fn host_in_routes() {
+        let s = session();
+        let parser = pos(&s, "(parser");
+        assert!(pos(&s, "(routes") < parser);
+        assert!(parser < pos(&s, "(library_out"));
+        assert!(parser < pos(&s, "\"memeroute\""));
+        assert!(parser < pos(&s, "\"1.0\""));
+    }
+
+    #[test]
+    fn only_used_vias_in_library_out() {
+        let s = session();
+        assert!(pos(&s, "(library_out") < pos(&s, "\"via600\""));
+        assert!(!s.contains("\"unused_via\""));
+    }
+
+    // Converts |pcb| with a single wire of net A, collapsing whitespace.
+    fn wire_session(shape: Shape) -> String {
+        let mut pcb = test_pcb();
+        let net_id = pcb.to_id("A");
+        pcb.add_wire(Wire { shape: LayerShape { layers: LayerSet::one(0), shape }, net_id });
+        let s = PcbToSession::new(pcb).convert().unwrap();
+        s.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    #[test]
+    fn capsule_wire_is_path() {
+        let s = wire_session(cap(pt(1.0, 1.0), pt(3.0, 1.0), 0.3).shape());
+        assert!(s.contains("(wire (path \"F.Cu\" 60000 100000 100000 300000 100000))"), "{s}");
+    }
+
+    #[test]
+    fn segment_wire_uses_net_width() {
+        // Net A is in the "wide" class, with a width of 0.4 mm.
+        let s = wire_session(seg(pt(1.0, 1.0), pt(3.0, 1.0)).shape());
+        assert!(s.contains("(wire (path \"F.Cu\" 40000 100000 100000 300000 100000))"), "{s}");
+    }
+
+    #[test]
+    fn precision() {
+        let mut w = PcbToSession::new(test_pcb());
+        assert!(w.set_precision(40).is_err());
+        w.set_precision(3).unwrap();
+        let s = w.convert().unwrap();
+        assert!(s.contains("(resolution mm 1000)"), "{s}");
+        // R1 is at (5, 10) mm.
+        assert!(s.contains("\"R1\" 5000 10000 front"), "{s}");
+    }
+
+    #[test]
+    fn two_width_wire_is_two_paths() {
+        let mut pcb = test_pcb();
+        let net_id = pcb.to_id("A");
+        let pts = [pt(1.0, 1.0), pt(2.0, 1.0), pt(3.0, 1.0)];
+        for wire in Wire::with_radii(net_id, LayerSet::one(0), &pts, &[0.1, 0.2]).unwrap() {
+            pcb.add_wire(wire);
+        }
+        let s = PcbToSession::new(pcb).convert().unwrap();
+        let s = s.split_whitespace().collect::<Vec<_>>().join(" ");
+        assert!(s.contains("(wire (path \"F.Cu\" 20000 100000 100000 200000 100000))"), "{s}");
+        assert!(s.contains("(wire (path \"F.Cu\" 40000 200000 100000 300000 100000))"), "{s}");
+    }
+
+    // The balanced clause of |s| starting at |pat|.
+    fn clause<'a>(s: &'a str, pat: &str) -> &'a str {
+        let start = pos(s, pat);
+        let mut depth = 0;
+        for (i, c) in s[start..].char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => continue,
+            }
+            if depth == 0 {
+                return &s[start..=start + i];
+            }
+        }
+        panic!("unterminated {pat} in {s}");
+    }
+
+    #[test]
+    fn keepouts_and_planes_round_trip() {
+        let mut pcb = test_pcb();
+        let net_id = pcb.to_id("B");
+        let shape = rt(0.0, 0.0, 20.0, 20.0).shape();
+        let shape = LayerShape { layers: LayerSet::one(1), shape };
+        let windows = vec![circ(pt(10.0, 15.0), 0.6).shape()];
+        pcb.add_plane(Plane { net_id, shape, windows });
+        let shape = rt(1.0, 1.0, 2.0, 2.0).shape();
+        let shape = LayerShape { layers: pcb.copper_layers(), shape };
+        let windows = vec![rt(1.2, 1.2, 1.8, 1.8).shape()];
+        pcb.add_keepout(Keepout { kind: KeepoutType::WireKeepout, shape, windows });
+        let mut w = PcbToSession::new(pcb);
+        w.set_precision(3).unwrap(); // Output in um, like TEST_DSN.
+        let s = w.convert().unwrap();
+        let structure = pos(&s, "(structure_out");
+        assert!(pos(&s, "(routes") < structure && structure < pos(&s, "(library_out"));
+
+        // Put the clauses back into a design and check they convert to the same thing.
+        let keepout = clause(&s, "(wire_keepout");
+        let plane = clause(&s[pos(&s, "(net \"B\"")..], "(wire").replacen("(wire", "(plane B", 1);
+        let dsn = TEST_DSN.replace("(via via600)", &format!("(via via600)\n{keepout}\n{plane}"));
+        let pcb = load_dsn(&dsn).unwrap();
+        assert_eq!(pcb.keepouts().len(), 1);
+        let k = &pcb.keepouts()[0];
+        assert!(matches!(k.kind, KeepoutType::WireKeepout));
+        assert_eq!(k.shape.layers, pcb.copper_layers());
+        let b = k.shape.shape.bounds();
+        assert!(eq(b.l(), 1.0) && eq(b.b(), 1.0) && eq(b.r(), 2.0) && eq(b.t(), 2.0), "{b:?}");
+        assert_eq!(k.windows.len(), 1);
+        assert_eq!(pcb.planes().len(), 1);
+        let p = &pcb.planes()[0];
+        assert_eq!(p.net_id, pcb.to_id("B"));
+        assert_eq!(p.shape.layers, LayerSet::one(1));
+        let b = p.windows[0].bounds();
+        assert!(eq(b.l(), 9.4) && eq(b.r(), 10.6) && eq(b.t(), 15.6), "{b:?}");
     }
 }
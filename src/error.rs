@@ -0,0 +1,32 @@
+use derive_more::Display;
+
+use crate::name::Id;
+
+// Errors returned from the public entry points, so callers can tell what kind
+// of failure happened. Internally, eyre is used and the report is kept as the
+// message.
+#[must_use]
+#[derive(Debug, Display, Clone, PartialEq, Eq)]
+pub enum MemerouteError {
+    #[display(fmt = "parse error: {_0}")]
+    ParseError(String),
+    #[display(fmt = "conversion error: {_0}")]
+    ConversionError(String),
+    #[display(fmt = "routing error: {msg} (failed nets: {failed_nets:?})")]
+    RoutingError { msg: String, failed_nets: Vec<Id> },
+    #[display(fmt = "geometry error: {_0}")]
+    GeometryError(String),
+}
+
+impl std::error::Error for MemerouteError {}
+
+impl MemerouteError {
+    // Converts an internal error. If it was created from a MemerouteError,
+    // that is returned, otherwise |f| is used to construct one from the message.
+    pub(crate) fn from_report(e: eyre::Report, f: impl FnOnce(String) -> Self) -> Self {
+        match e.downcast::<MemerouteError>() {
+            Ok(v) => v,
+            Err(e) => f(format!("{e:#}")),
+        }
+    }
+}
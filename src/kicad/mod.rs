@@ -0,0 +1 @@
+pub mod pcb_to_kicad;
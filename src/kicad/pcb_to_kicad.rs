@@ -0,0 +1,126 @@
+use std::fmt::Write;
+
+use eyre::Result;
+
+use crate::model::pcb::Pcb;
+
+// Exports component placement and connectivity in formats KiCad can consume.
+#[must_use]
+#[derive(Debug, Clone)]
+pub struct PcbToKicad {
+    pcb: Pcb,
+}
+
+impl PcbToKicad {
+    pub fn new(pcb: Pcb) -> Self {
+        Self { pcb }
+    }
+
+    // Quotes a string in the netlist s-expression format.
+    fn quote(s: &str) -> String {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+
+    // Quotes a CSV field, doubling any quotes inside it.
+    fn csv_quote(s: &str) -> String {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    }
+
+    // Component position file, in the same CSV format as KiCad's footprint
+    // position export. There are no component values, so the footprint is used.
+    pub fn positions(&self) -> Result<String> {
+        let mut s = "Ref,Val,Package,PosX,PosY,Rot,Side\n".to_string();
+        for row in self.pcb.pick_and_place() {
+            let side = if row.flipped { "bottom" } else { "top" };
+            writeln!(
+                s,
+                "{},{},{},{:.4},{:.4},{:.4},{}",
+                Self::csv_quote(&row.refdes),
+                Self::csv_quote(&row.footprint),
+                Self::csv_quote(&row.footprint),
+                row.x,
+                row.y,
+                row.rotation,
+                side
+            )?;
+        }
+        Ok(s)
+    }
+
+    // Netlist in KiCad's s-expression netlist format.
+    pub fn netlist(&self) -> Result<String> {
+        let pcb = &self.pcb;
+        let mut s = "(export (version \"E\")\n  (components".to_string();
+        for row in pcb.pick_and_place() {
+            write!(
+                s,
+                "\n    (comp (ref {}) (value {}) (footprint {}))",
+                Self::quote(&row.refdes),
+                Self::quote(&row.footprint),
+                Self::quote(&row.footprint)
+            )?;
+        }
+        s += ")\n  (nets";
+
+        let mut nets: Vec<_> = pcb.nets().map(|n| (pcb.to_name(n.id), n)).collect();
+        nets.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        for (code, (name, net)) in nets.into_iter().enumerate() {
+            write!(s, "\n    (net (code \"{}\") (name {})", code + 1, Self::quote(&name))?;
+            let mut pins: Vec<_> =
+                net.pins.iter().map(|p| (pcb.to_name(p.component), pcb.to_name(p.pin))).collect();
+            pins.sort_unstable();
+            for (component, pin) in pins {
+                write!(
+                    s,
+                    "\n      (node (ref {}) (pin {}))",
+                    Self::quote(&component),
+                    Self::quote(&pin)
+                )?;
+            }
+            s += ")";
+        }
+        s += "))\n";
+        Ok(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsn::load_dsn;
+    use crate::testing::{test_pcb, TEST_DSN};
+
+    #[test]
+    fn positions() {
+        let dsn =
+            TEST_DSN.replace("(place R2 15000 10000 front 0)", "(place R2 15000 10000 back 0)");
+        let s = PcbToKicad::new(load_dsn(&dsn).unwrap()).positions().unwrap();
+        let expected = "Ref,Val,Package,PosX,PosY,Rot,Side
+\"R1\",\"pad_image\",\"pad_image\",5.0000,10.0000,90.0000,top
+\"R2\",\"pad_image\",\"pad_image\",15.0000,10.0000,0.0000,bottom
+\"R3\",\"pad_image\",\"pad_image\",5.0000,5.0000,0.0000,top
+\"R4\",\"pad_image\",\"pad_image\",15.0000,5.0000,0.0000,top
+";
+        assert_eq!(s, expected);
+    }
+
+    #[test]
+    fn netlist() {
+        let s = PcbToKicad::new(test_pcb()).netlist().unwrap();
+        let expected = "(export (version \"E\")
+  (components
+    (comp (ref \"R1\") (value \"pad_image\") (footprint \"pad_image\"))
+    (comp (ref \"R2\") (value \"pad_image\") (footprint \"pad_image\"))
+    (comp (ref \"R3\") (value \"pad_image\") (footprint \"pad_image\"))
+    (comp (ref \"R4\") (value \"pad_image\") (footprint \"pad_image\")))
+  (nets
+    (net (code \"1\") (name \"A\")
+      (node (ref \"R1\") (pin \"1\"))
+      (node (ref \"R2\") (pin \"1\")))
+    (net (code \"2\") (name \"B\")
+      (node (ref \"R3\") (pin \"1\"))
+      (node (ref \"R4\") (pin \"1\")))))
+";
+        assert_eq!(s, expected);
+    }
+}
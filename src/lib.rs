@@ -39,6 +39,10 @@
 )]
 
 pub mod dsn;
+pub mod error;
+pub mod kicad;
 pub mod model;
 pub mod name;
 pub mod route;
+#[cfg(test)]
+mod testing;
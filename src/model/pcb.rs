@@ -1,17 +1,20 @@
+use std::cell::RefCell;
 use std::collections::hash_map::Values;
 use std::iter::FromIterator;
 use std::sync::RwLock;
 
-use ahash::HashMap;
+use ahash::{HashMap, HashSet};
 use auto_ops::{impl_op_ex, impl_op_ex_commutative};
 use enumset::{enum_set, EnumSet, EnumSetType};
 use eyre::{eyre, Result};
 use memegeom::geom::bounds::rt_cloud_bounds;
-use memegeom::geom::qt::query::Kinds;
+use memegeom::geom::math::eq;
+use memegeom::geom::qt::query::{Kinds, KindsQuery, Query, ShapeInfo, TagQuery, NO_TAG};
+use memegeom::primitive::compound::Compound;
 use memegeom::primitive::point::Pt;
 use memegeom::primitive::rect::Rt;
 use memegeom::primitive::shape::Shape;
-use memegeom::primitive::{pt, ShapeOps};
+use memegeom::primitive::{circ, path, pt, ShapeOps};
 use memegeom::tf::Tf;
 use rust_dense_bitset::{BitSet, DenseBitSet};
 use strum::EnumIter;
@@ -32,6 +35,17 @@ pub enum LayerKind {
     Power,
     Mixed,
     Jumper,
+    // Non-copper layers. These are kept for rendering but never routed on.
+    SolderMask,
+    Paste,
+    Silk,
+}
+
+impl LayerKind {
+    #[must_use]
+    pub fn is_copper(&self) -> bool {
+        !matches!(self, LayerKind::SolderMask | LayerKind::Paste | LayerKind::Silk)
+    }
 }
 
 // Support up to 64 layers.
@@ -189,6 +203,7 @@ pub enum KeepoutType {
 pub struct Keepout {
     pub kind: KeepoutType,
     pub shape: LayerShape,
+    pub windows: Vec<Shape>, // Cutouts in the keepout which are allowed to be routed through.
 }
 
 impl Keepout {
@@ -197,6 +212,16 @@ impl Keepout {
     }
 }
 
+// Describes a copper plane (pour) connected to a net. Windows are cutouts,
+// e.g. antipads around vias of other nets.
+#[must_use]
+#[derive(Debug, Clone)]
+pub struct Plane {
+    pub net_id: Id,
+    pub shape: LayerShape,
+    pub windows: Vec<Shape>,
+}
+
 // Describes a pin.
 #[must_use]
 #[derive(Debug, Default, Clone)]
@@ -217,6 +242,37 @@ impl Pin {
     }
 }
 
+// Describes where the reference designator text of a component goes.
+#[must_use]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Designator {
+    pub p: Pt,
+    pub rotation: f64,
+}
+
+// Mirroring of a component's geometry, independent of which side it's on.
+// X mirrors about the x axis, and Y about the y axis.
+#[must_use]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Mirror {
+    #[default]
+    None,
+    X,
+    Y,
+    XY,
+}
+
+impl Mirror {
+    pub fn tf(&self) -> Tf {
+        match self {
+            Mirror::None => Tf::identity(),
+            Mirror::X => Tf::scale(pt(1.0, -1.0)),
+            Mirror::Y => Tf::scale(pt(-1.0, 1.0)),
+            Mirror::XY => Tf::scale(pt(-1.0, -1.0)),
+        }
+    }
+}
+
 // Describes a component at a location.
 #[must_use]
 #[derive(Debug, Default, Clone)]
@@ -226,6 +282,8 @@ pub struct Component {
     pub footprint_id: Id,
     pub p: Pt,
     pub rotation: f64,
+    pub designator: Option<Designator>, // Used for assembly output.
+    pub mirror: Mirror,
     pub outlines: Vec<LayerShape>,
     pub keepouts: Vec<Keepout>,
     pins: HashMap<Id, Pin>,
@@ -250,7 +308,7 @@ impl Component {
     pub fn tf(&self) -> Tf {
         // Being on the back mirrors, i.e. horizontal flip.
         let side_tf = if self.flipped { Tf::scale(pt(-1.0, 1.0)) } else { Tf::identity() };
-        Tf::translate(self.p) * Tf::rotate(self.rotation) * side_tf
+        Tf::translate(self.p) * Tf::rotate(self.rotation) * side_tf * self.mirror.tf()
     }
 
     pub fn flip(&mut self, num_layers: usize) {
@@ -286,6 +344,18 @@ impl Padstack {
         self.shapes.iter().map(|s| s.layers).collect()
     }
 
+    // Whether |other| has the same shapes on the same layers, ignoring ids.
+    // Shapes can't be compared directly, so this compares their debug output,
+    // which has all of their geometry.
+    #[must_use]
+    pub fn same_shapes(&self, other: &Padstack) -> bool {
+        self.attach == other.attach
+            && self.shapes.len() == other.shapes.len()
+            && self.shapes.iter().zip(&other.shapes).all(|(a, b)| {
+                a.layers == b.layers && format!("{:?}", a.shape) == format!("{:?}", b.shape)
+            })
+    }
+
     pub fn flip(&mut self, num_layers: usize) {
         for v in &mut self.shapes {
             v.flip(num_layers);
@@ -293,6 +363,18 @@ impl Padstack {
     }
 }
 
+// One row of a pick and place (assembly) file.
+#[must_use]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PnpRow {
+    pub refdes: String,
+    pub footprint: String,
+    pub x: f64,
+    pub y: f64,
+    pub rotation: f64,
+    pub flipped: bool, // True if on the back side.
+}
+
 #[must_use]
 #[derive(Debug, Hash, PartialEq, Eq, Default, Clone)]
 pub struct PinRef {
@@ -306,11 +388,42 @@ impl PinRef {
     }
 }
 
+// Kind of supply a net carries, if any.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Supply {
+    Power,
+    Ground,
+}
+
 #[must_use]
 #[derive(Debug, Default, Clone)]
 pub struct Net {
     pub id: Id,
     pub pins: Vec<PinRef>,
+    pub fixed: bool, // Fixed nets keep their existing wires and are not routed.
+    pub supply: Option<Supply>,
+}
+
+impl Net {
+    #[must_use]
+    pub fn is_fixed(&self) -> bool {
+        self.fixed
+    }
+
+    pub fn supply(&self) -> Option<Supply> {
+        self.supply
+    }
+
+    #[must_use]
+    pub fn is_power(&self) -> bool {
+        self.supply == Some(Supply::Power)
+    }
+
+    #[must_use]
+    pub fn is_ground(&self) -> bool {
+        self.supply == Some(Supply::Ground)
+    }
 }
 
 // Describes a route.
@@ -321,6 +434,44 @@ pub struct Wire {
     pub net_id: Id,
 }
 
+impl Wire {
+    // Length of the wire's centerline. Only paths have a length.
+    #[must_use]
+    pub fn length(&self) -> f64 {
+        match &self.shape.shape {
+            Shape::Path(p) => p.pts().windows(2).map(|v| v[0].dist(v[1])).sum(),
+            _ => 0.0,
+        }
+    }
+
+    // Creates wires along |pts| where segment i has radius |radii[i]|, e.g. for
+    // tapers. Consecutive segments with the same radius share a wire.
+    pub fn with_radii(
+        net_id: Id,
+        layers: LayerSet,
+        pts: &[Pt],
+        radii: &[f64],
+    ) -> Result<Vec<Wire>> {
+        if pts.len() != radii.len() + 1 {
+            return Err(eyre!(
+                "need one radius per segment, got {} points and {} radii",
+                pts.len(),
+                radii.len()
+            ));
+        }
+        let mut wires = Vec::new();
+        let mut start = 0;
+        for i in 1..=radii.len() {
+            if i == radii.len() || !eq(radii[i], radii[start]) {
+                let shape = path(&pts[start..=i], radii[start]).shape();
+                wires.push(Wire { shape: LayerShape { layers, shape }, net_id });
+                start = i;
+            }
+        }
+        Ok(wires)
+    }
+}
+
 // Describes a via.
 #[must_use]
 #[derive(Debug, Clone)]
@@ -336,6 +487,20 @@ impl Via {
     }
 }
 
+// Expands a via into a |rows| by |cols| grid of vias with the given pitch,
+// starting at the template's location and going in the positive x and y directions.
+#[must_use]
+pub fn expand_via_array(template: &Via, rows: usize, cols: usize, pitch: f64) -> Vec<Via> {
+    let mut vias = Vec::with_capacity(rows * cols);
+    for row in 0..rows {
+        for col in 0..cols {
+            let p = template.p + pt(col as f64 * pitch, row as f64 * pitch);
+            vias.push(Via { p, ..template.clone() });
+        }
+    }
+    vias
+}
+
 // Object kinds
 #[must_use]
 #[derive(Debug, EnumSetType, EnumIter)]
@@ -387,6 +552,19 @@ impl Clearance {
         }
     }
 
+    // Whether this clearance applies between objects of kinds |a| and |b|.
+    #[must_use]
+    pub fn applies(&self, a: ObjectKind, b: ObjectKind) -> bool {
+        let kinds = match a {
+            ObjectKind::Area => self.area_kinds,
+            ObjectKind::Pin => self.pin_kinds,
+            ObjectKind::Smd => self.smd_kinds,
+            ObjectKind::Via => self.via_kinds,
+            ObjectKind::Wire => self.wire_kinds,
+        };
+        kinds.contains(b)
+    }
+
     fn subset_for_mut(&mut self, kind: ObjectKind) -> &mut EnumSet<ObjectKind> {
         match kind {
             ObjectKind::Area => &mut self.area_kinds,
@@ -410,6 +588,7 @@ pub enum Rule {
     Radius(f64),          // e.g. Half-width of track
     Clearance(Clearance), // e.g. Minimum distance between track and via.
     UseVia(Id),           // Use the specified via if this rule applies.
+    UseLayers(LayerSet),  // Only route on the specified layers if this rule applies.
 }
 
 // Collection of rules that e.g. may apply to a given net.
@@ -420,11 +599,13 @@ pub struct RuleSet {
     radius: Option<f64>,
     clearances: Vec<Clearance>,
     use_via: Option<Id>,
+    allowed_layers: Option<LayerSet>,
 }
 
 impl RuleSet {
     pub fn new(id: Id, rules: Vec<Rule>) -> Result<Self> {
-        let mut rs = Self { id, radius: None, clearances: Vec::new(), use_via: None };
+        let mut rs =
+            Self { id, radius: None, clearances: Vec::new(), use_via: None, allowed_layers: None };
         // Check for consistency:
         for rule in rules {
             match rule {
@@ -441,6 +622,12 @@ impl RuleSet {
                     }
                     rs.use_via = Some(v);
                 }
+                Rule::UseLayers(l) => {
+                    if rs.allowed_layers.is_some() {
+                        return Err(eyre!("Multple use_layer rules"));
+                    }
+                    rs.allowed_layers = Some(l);
+                }
             }
         }
 
@@ -448,8 +635,8 @@ impl RuleSet {
     }
 
     #[must_use]
-    pub fn radius(&self) -> f64 {
-        self.radius.unwrap()
+    pub fn radius(&self) -> Option<f64> {
+        self.radius
     }
 
     pub fn clearances(&self) -> &[Clearance] {
@@ -460,6 +647,22 @@ impl RuleSet {
     pub fn use_via(&self) -> Option<Id> {
         self.use_via
     }
+
+    // Layers nets with this ruleset may be routed on. None means any layer.
+    #[must_use]
+    pub fn allowed_layers(&self) -> Option<LayerSet> {
+        self.allowed_layers
+    }
+}
+
+// Rules which apply to a net after merging its ruleset with the defaults.
+#[must_use]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedRules {
+    pub radius: HashMap<LayerId, f64>, // Wire radius on each allowed layer.
+    pub clearances: Vec<Clearance>,
+    pub use_via: Option<Id>,
+    pub allowed_layers: LayerSet,
 }
 
 // Describes an overall PCB.
@@ -473,22 +676,30 @@ pub struct Pcb {
     layers: Vec<Layer>,
     boundaries: Vec<LayerShape>,
     keepouts: Vec<Keepout>,
+    planes: Vec<Plane>,
     via_padstacks: Vec<Padstack>, // Types of vias available to use.
     components: HashMap<Id, Component>,
 
     // Routing:
     wires: Vec<Wire>,
     vias: Vec<Via>,
+    net_wires: HashMap<Id, Vec<usize>>, // Map net ID to indices into |wires|.
+    net_vias: HashMap<Id, Vec<usize>>,  // Map net ID to indices into |vias|.
     nets: HashMap<Id, Net>,
     pin_ref_to_net: HashMap<PinRef, Id>, // Map PinRef to net ID.
+    diff_pairs: Vec<(Id, Id)>,           // Pairs of nets routed as differential pairs.
 
     // Rules:
     rulesets: HashMap<Id, RuleSet>,
     net_to_ruleset: HashMap<Id, Id>,
     default_net_ruleset: Id,
+    layer_radius: HashMap<LayerId, f64>, // Width rules from layer descriptors.
+    default_radius: Option<f64>,         // Width rule from the structure.
+    wire_grid: Option<f64>,              // Declared routing grid spacing.
 
     // Debug:
     debug_rts: Vec<Rt>,
+    diagnostics: Vec<String>, // Unsupported features skipped while loading.
 }
 
 impl Clone for Pcb {
@@ -499,16 +710,24 @@ impl Clone for Pcb {
             layers: self.layers.clone(),
             boundaries: self.boundaries.clone(),
             keepouts: self.keepouts.clone(),
+            planes: self.planes.clone(),
             via_padstacks: self.via_padstacks.clone(),
             components: self.components.clone(),
             wires: self.wires.clone(),
             vias: self.vias.clone(),
+            net_wires: self.net_wires.clone(),
+            net_vias: self.net_vias.clone(),
             nets: self.nets.clone(),
             pin_ref_to_net: self.pin_ref_to_net.clone(),
+            diff_pairs: self.diff_pairs.clone(),
             rulesets: self.rulesets.clone(),
             net_to_ruleset: self.net_to_ruleset.clone(),
             default_net_ruleset: self.default_net_ruleset,
+            layer_radius: self.layer_radius.clone(),
+            default_radius: self.default_radius,
+            wire_grid: self.wire_grid,
             debug_rts: self.debug_rts.clone(),
+            diagnostics: self.diagnostics.clone(),
         }
     }
 }
@@ -530,6 +749,44 @@ impl Pcb {
         }
     }
 
+    // Layers which can be routed on.
+    pub fn copper_layers(&self) -> LayerSet {
+        self.layers().iter().filter(|l| l.kind.is_copper()).map(|v| v.layer_id).collect()
+    }
+
+    // Checks layers are numbered contiguously from 0 in stackup order, which
+    // e.g. LayerSet::flip relies on.
+    pub fn validate_layers(&self) -> Result<()> {
+        if self.layers.len() > 64 {
+            return Err(eyre!("too many layers: {}", self.layers.len()));
+        }
+        let mut seen: HashMap<LayerId, Id> = HashMap::default();
+        for l in &self.layers {
+            if let Some(prev) = seen.insert(l.layer_id, l.name_id) {
+                return Err(eyre!(
+                    "layers {} and {} both have id {}",
+                    self.to_name(prev),
+                    self.to_name(l.name_id),
+                    l.layer_id
+                ));
+            }
+        }
+        if let Some(missing) = (0..self.layers.len()).find(|id| !seen.contains_key(id)) {
+            return Err(eyre!("layer ids are not contiguous, missing id {}", missing));
+        }
+        for (idx, l) in self.layers.iter().enumerate() {
+            if l.layer_id != idx {
+                return Err(eyre!(
+                    "layer {} has id {} but is at stackup position {}",
+                    self.to_name(l.name_id),
+                    l.layer_id,
+                    idx
+                ));
+            }
+        }
+        Ok(())
+    }
+
     pub fn layer_by_id(&self, lid: LayerId) -> &Layer {
         self.layers().iter().find(|l| l.layer_id == lid).unwrap()
     }
@@ -548,10 +805,431 @@ impl Pcb {
         self.pin_ref_to_net.get(p).copied()
     }
 
+    // Returns the ids of all padstacks referenced by pins or vias.
+    #[must_use]
+    pub fn used_padstacks(&self) -> HashSet<Id> {
+        let pins = self.components().flat_map(|c| c.pins().map(|p| p.padstack.id));
+        let vias = self.vias().iter().map(|v| v.padstack.id);
+        pins.chain(vias).collect()
+    }
+
+    // Returns via padstacks which are defined but not used by any pin or via.
+    #[must_use]
+    pub fn unused_via_padstacks(&self) -> Vec<&Padstack> {
+        let used = self.used_padstacks();
+        self.via_padstacks().iter().filter(|p| !used.contains(&p.id)).collect()
+    }
+
+    // Returns the world space shapes of the padstack of every pin, i.e. with
+    // the component and pin transforms applied.
+    #[must_use]
+    pub fn pin_world_shapes(&self) -> HashMap<PinRef, Vec<LayerShape>> {
+        let mut res = HashMap::default();
+        for c in self.components() {
+            for pin in c.pins() {
+                let tf = c.tf() * pin.tf();
+                let shapes = pin
+                    .padstack
+                    .shapes
+                    .iter()
+                    .map(|v| LayerShape { layers: v.layers, shape: tf.shape(&v.shape) })
+                    .collect();
+                res.insert(PinRef::new(c, pin), shapes);
+            }
+        }
+        res
+    }
+
+    // Returns pick and place rows for all components, sorted by reference designator.
+    #[must_use]
+    pub fn pick_and_place(&self) -> Vec<PnpRow> {
+        let mut rows: Vec<_> = self
+            .components()
+            .map(|c| PnpRow {
+                refdes: self.to_name(c.id),
+                footprint: self.to_name(c.footprint_id),
+                x: c.p.x,
+                y: c.p.y,
+                rotation: c.rotation,
+                flipped: c.flipped(),
+            })
+            .collect();
+        rows.sort_unstable_by(|a, b| a.refdes.cmp(&b.refdes));
+        rows
+    }
+
     pub fn bounds(&self) -> Rt {
         // Assumes boundaries are valid.
         rt_cloud_bounds(self.boundaries().iter().map(|v| v.shape.bounds()))
     }
+
+    // Bounds of everything on the PCB, not just the boundaries. Useful for boards
+    // without an explicit boundary.
+    pub fn content_bounds(&self) -> Rt {
+        let mut rts: Vec<Rt> = self.boundaries().iter().map(|v| v.shape.bounds()).collect();
+        for c in self.components() {
+            let tf = c.tf();
+            rts.extend(c.outlines.iter().map(|v| tf.shape(&v.shape).bounds()));
+            for pin in c.pins() {
+                let tf = tf * pin.tf();
+                rts.extend(pin.padstack.shapes.iter().map(|v| tf.shape(&v.shape).bounds()));
+            }
+        }
+        rts.extend(self.wires().iter().map(|v| v.shape.shape.bounds()));
+        for via in self.vias() {
+            let tf = via.tf();
+            rts.extend(via.padstack.shapes.iter().map(|v| tf.shape(&v.shape).bounds()));
+        }
+        rt_cloud_bounds(rts.into_iter())
+    }
+
+    // Length of the Euclidean minimum spanning tree over the centers of the pins
+    // of the given net. This is a lower bound-ish estimate of the wire length
+    // needed to route the net.
+    #[must_use]
+    pub fn net_mst_length(&self, id: Id) -> f64 {
+        let Some(net) = self.net(id) else { return 0.0 };
+        let pts: Vec<Pt> = net
+            .pins
+            .iter()
+            .filter_map(|p| self.pin_ref(p).ok())
+            .map(|(c, p)| (c.tf() * p.tf()).pt(Pt::zero()))
+            .collect();
+        if pts.is_empty() {
+            return 0.0;
+        }
+
+        // Prim's algorithm. Nets are small so O(n^2) is fine.
+        let mut dist: Vec<f64> = pts.iter().map(|p| p.dist(pts[0])).collect();
+        let mut done = vec![false; pts.len()];
+        done[0] = true;
+        let mut total = 0.0;
+        for _ in 1..pts.len() {
+            let (next, d) = dist
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| !done[i])
+                .min_by(|a, b| a.1.total_cmp(b.1))
+                .map(|(i, &d)| (i, d))
+                .unwrap();
+            done[next] = true;
+            total += d;
+            for (i, v) in dist.iter_mut().enumerate() {
+                *v = v.min(pts[i].dist(pts[next]));
+            }
+        }
+        total
+    }
+
+    // Estimates the fraction of the board area on |layer| covered by copper,
+    // i.e. pads, wires, vias, and planes. This samples the center of each cell
+    // of a 200x200 grid over the boundary rather than computing exact areas,
+    // since overlapping copper and plane windows would need boolean clipping.
+    // Only cells crossed by an edge of the copper can be wrong, so the error
+    // is at most the length of the copper edges times the cell diagonal,
+    // divided by the board area. Copper other than planes goes in a quadtree
+    // so each sample only looks at nearby shapes.
+    #[must_use]
+    pub fn copper_ratio(&self, layer: LayerId) -> f64 {
+        const SAMPLES: usize = 200; // Number of samples along each axis.
+        let on_layer = |v: &LayerShape| v.layers.contains(layer);
+        let boundaries: Vec<&Shape> =
+            self.boundaries().iter().filter(|v| on_layer(v)).map(|v| &v.shape).collect();
+        if boundaries.is_empty() {
+            return 0.0;
+        }
+
+        let b = rt_cloud_bounds(boundaries.iter().map(|v| v.bounds()));
+        let mut copper = Compound::with_bounds(&b);
+        let mut add = |s: Shape| {
+            copper.add_shape(ShapeInfo::new(s, NO_TAG, ObjectKind::Area.query()));
+        };
+        for v in self.pin_world_shapes().into_values().flatten().filter(on_layer) {
+            add(v.shape);
+        }
+        for v in self.wires().iter().filter(|v| on_layer(&v.shape)) {
+            add(v.shape.shape.clone());
+        }
+        for via in self.vias() {
+            let tf = via.tf();
+            for v in via.padstack.shapes.iter().filter(|v| on_layer(v)) {
+                add(tf.shape(&v.shape));
+            }
+        }
+        // Planes can have windows, which the quadtree can't represent.
+        let planes: Vec<&Plane> = self.planes().iter().filter(|v| on_layer(&v.shape)).collect();
+
+        let (mut inside, mut covered) = (0, 0);
+        for i in 0..SAMPLES {
+            for j in 0..SAMPLES {
+                let x = b.l() + (b.r() - b.l()) * (i as f64 + 0.5) / SAMPLES as f64;
+                let y = b.b() + (b.t() - b.b()) * (j as f64 + 0.5) / SAMPLES as f64;
+                let p = circ(pt(x, y), 0.0).shape();
+                if !boundaries.iter().any(|v| v.intersects_shape(&p)) {
+                    continue;
+                }
+                inside += 1;
+                let in_plane = |v: &&Plane| {
+                    v.shape.shape.intersects_shape(&p)
+                        && !v.windows.iter().any(|w| w.intersects_shape(&p))
+                };
+                if copper.intersects(&p, Query(TagQuery::All, KindsQuery::All))
+                    || planes.iter().any(in_plane)
+                {
+                    covered += 1;
+                }
+            }
+        }
+        if inside == 0 {
+            0.0
+        } else {
+            covered as f64 / inside as f64
+        }
+    }
+
+    // Rounds the location of every component to the nearest multiple of |grid|.
+    // Pins and designators move along with their component. |grid| must be positive.
+    pub fn snap_placements(&mut self, grid: f64) -> Result<()> {
+        if !grid.is_finite() || grid <= 0.0 {
+            return Err(eyre!("snap grid must be positive, got {}", grid));
+        }
+        for c in self.components.values_mut() {
+            let p = pt((c.p.x / grid).round() * grid, (c.p.y / grid).round() * grid);
+            if let Some(d) = &mut c.designator {
+                d.p = d.p + (p - c.p);
+            }
+            c.p = p;
+        }
+        Ok(())
+    }
+
+    // Translates everything so the bottom left of the board's bounds is at the
+    // origin. Boards far from the origin lose float precision.
+    pub fn normalize_origin(&mut self) {
+        let b = if self.boundaries.is_empty() { self.content_bounds() } else { self.bounds() };
+        let offset = Pt::zero() - b.bl();
+        let tf = Tf::translate(offset);
+        for v in &mut self.boundaries {
+            v.shape = tf.shape(&v.shape);
+        }
+        for v in &mut self.keepouts {
+            v.shape.shape = tf.shape(&v.shape.shape);
+            v.windows = v.windows.iter().map(|w| tf.shape(w)).collect();
+        }
+        for v in &mut self.planes {
+            v.shape.shape = tf.shape(&v.shape.shape);
+            v.windows = v.windows.iter().map(|w| tf.shape(w)).collect();
+        }
+        for c in self.components.values_mut() {
+            c.p = c.p + offset;
+            if let Some(d) = &mut c.designator {
+                d.p = d.p + offset;
+            }
+        }
+        for v in &mut self.wires {
+            v.shape.shape = tf.shape(&v.shape.shape);
+        }
+        for v in &mut self.vias {
+            v.p = v.p + offset;
+        }
+    }
+
+    // Picks a prefix for names merged from another PCB which no component or
+    // net name in this PCB starts with, e.g. "1/".
+    fn merge_prefix(&self) -> String {
+        let names: Vec<_> =
+            self.components.keys().chain(self.nets.keys()).map(|&v| self.to_name(v)).collect();
+        (1..).map(|n| format!("{n}/")).find(|p| !names.iter().any(|v| v.starts_with(p))).unwrap()
+    }
+
+    // Merges |other| into this PCB translated by |offset|, e.g. for panelization.
+    // Names of components and nets from |other| are prefixed with a namespace
+    // like "1/" which isn't used yet, so they don't collide. Layers are matched
+    // by name and must exist in this PCB. Padstacks and classes are shared by
+    // name. Via padstacks, classes and layer widths must match any already in
+    // this PCB. On error, this PCB is left unchanged.
+    pub fn merge(&mut self, other: &Pcb, offset: Pt) -> Result<()> {
+        let mut layer_map: HashMap<LayerId, LayerId> = HashMap::default();
+        for l in other.layers() {
+            let name = other.to_name(l.name_id);
+            let to = self
+                .layers()
+                .iter()
+                .find(|v| self.to_name(v.name_id) == name)
+                .ok_or_else(|| eyre!("layer {} missing when merging", name))?;
+            layer_map.insert(l.layer_id, to.layer_id);
+        }
+
+        // Add names to a copy of the name map, so it's unchanged on error.
+        let prefix = self.merge_prefix();
+        let names = RefCell::new(self.name_map.read().unwrap().clone());
+        let name = |v: Id| names.borrow().name(v).to_owned();
+        let tf = Tf::translate(offset);
+        let id = |v: Id| names.borrow_mut().name_to_id(&other.to_name(v));
+        let prefixed =
+            |v: Id| names.borrow_mut().name_to_id(&format!("{prefix}{}", other.to_name(v)));
+        let layers = |v: LayerSet| v.iter().map(|l| layer_map[&l]).collect::<LayerSet>();
+        let local =
+            |v: &LayerShape| LayerShape { layers: layers(v.layers), shape: v.shape.clone() };
+        let world =
+            |v: &LayerShape| LayerShape { layers: layers(v.layers), shape: tf.shape(&v.shape) };
+        let padstack = |v: &Padstack| Padstack {
+            id: id(v.id),
+            shapes: v.shapes.iter().map(local).collect(),
+            attach: v.attach,
+        };
+        let keepout = |v: &Keepout, tf: &Tf| Keepout {
+            kind: v.kind.clone(),
+            shape: LayerShape { layers: layers(v.shape.layers), shape: tf.shape(&v.shape.shape) },
+            windows: v.windows.iter().map(|w| tf.shape(w)).collect(),
+        };
+
+        let boundaries: Vec<_> = other.boundaries().iter().map(world).collect();
+        let keepouts: Vec<_> = other.keepouts().iter().map(|v| keepout(v, &tf)).collect();
+        let planes: Vec<_> = other
+            .planes()
+            .iter()
+            .map(|v| Plane {
+                net_id: prefixed(v.net_id),
+                shape: world(&v.shape),
+                windows: v.windows.iter().map(|w| tf.shape(w)).collect(),
+            })
+            .collect();
+        let via_padstacks: Vec<_> = other.via_padstacks().iter().map(padstack).collect();
+        let components: Vec<_> = other
+            .components()
+            .map(|c| Component {
+                id: prefixed(c.id),
+                footprint_id: id(c.footprint_id),
+                p: c.p + offset,
+                rotation: c.rotation,
+                designator: c.designator.map(|d| Designator { p: d.p + offset, ..d }),
+                mirror: c.mirror,
+                outlines: c.outlines.iter().map(local).collect(),
+                keepouts: c.keepouts.iter().map(|v| keepout(v, &Tf::identity())).collect(),
+                pins: c
+                    .pins()
+                    .map(|p| {
+                        let p = Pin { id: id(p.id), padstack: padstack(&p.padstack), ..p.clone() };
+                        (p.id, p)
+                    })
+                    .collect(),
+                flipped: c.flipped,
+            })
+            .collect();
+        let wires: Vec<_> = other
+            .wires()
+            .iter()
+            .map(|v| Wire { shape: world(&v.shape), net_id: prefixed(v.net_id) })
+            .collect();
+        let vias: Vec<_> = other
+            .vias()
+            .iter()
+            .map(|v| Via {
+                p: v.p + offset,
+                padstack: padstack(&v.padstack),
+                net_id: prefixed(v.net_id),
+            })
+            .collect();
+        let nets: Vec<_> = other
+            .nets()
+            .map(|n| Net {
+                id: prefixed(n.id),
+                pins: n
+                    .pins
+                    .iter()
+                    .map(|p| PinRef { component: prefixed(p.component), pin: id(p.pin) })
+                    .collect(),
+                fixed: n.fixed,
+                supply: n.supply,
+            })
+            .collect();
+        let net_rulesets: Vec<_> =
+            other.nets().map(|n| (prefixed(n.id), id(other.net_ruleset(n.id).id))).collect();
+        let rulesets: Vec<_> = other
+            .rulesets
+            .values()
+            .map(|r| RuleSet {
+                id: id(r.id),
+                use_via: r.use_via.map(id),
+                allowed_layers: r.allowed_layers.map(layers),
+                ..r.clone()
+            })
+            .collect();
+        let layer_radius: Vec<_> =
+            other.layer_radius.iter().map(|(l, r)| (layer_map[l], *r)).collect();
+        let diff_pairs: Vec<_> =
+            other.diff_pairs().iter().map(|&(a, b)| (prefixed(a), prefixed(b))).collect();
+
+        // Check for conflicts before changing anything.
+        for v in &components {
+            if self.components.contains_key(&v.id) {
+                return Err(eyre!("component {} already exists", name(v.id)));
+            }
+        }
+        for v in &nets {
+            if self.nets.contains_key(&v.id) {
+                return Err(eyre!("net {} already exists", name(v.id)));
+            }
+        }
+        for v in &via_padstacks {
+            if self.via_padstacks.iter().any(|p| p.id == v.id && !p.same_shapes(v)) {
+                return Err(eyre!("via padstack {} differs when merging", name(v.id)));
+            }
+        }
+        for v in &rulesets {
+            if self.rulesets.get(&v.id).is_some_and(|r| r != v) {
+                return Err(eyre!("class {} differs when merging", name(v.id)));
+            }
+        }
+        for &(l, r) in &layer_radius {
+            if self.layer_radius.get(&l).is_some_and(|&v| !eq(v, r)) {
+                let layer = self.to_name(self.layer_by_id(l).name_id);
+                return Err(eyre!("width of layer {} differs when merging", layer));
+            }
+        }
+
+        *self.name_map.get_mut().unwrap() = names.into_inner();
+        for v in boundaries {
+            self.add_boundary(v);
+        }
+        for v in keepouts {
+            self.add_keepout(v);
+        }
+        for v in planes {
+            self.add_plane(v);
+        }
+        for v in via_padstacks {
+            if !self.via_padstacks.iter().any(|p| p.id == v.id) {
+                self.add_via_padstack(v);
+            }
+        }
+        for v in components {
+            self.add_component(v);
+        }
+        for v in rulesets {
+            self.rulesets.entry(v.id).or_insert(v);
+        }
+        for (l, r) in layer_radius {
+            self.set_layer_radius(l, r);
+        }
+        for v in nets {
+            self.add_net(v);
+        }
+        for (net_id, ruleset_id) in net_rulesets {
+            self.set_net_ruleset(net_id, ruleset_id);
+        }
+        for (a, b) in diff_pairs {
+            self.add_diff_pair(a, b);
+        }
+        for v in wires {
+            self.add_wire(v);
+        }
+        for v in vias {
+            self.add_via(v);
+        }
+        Ok(())
+    }
 }
 
 // Getting and setting
@@ -576,11 +1254,82 @@ impl Pcb {
         self.net_to_ruleset.insert(net_id, ruleset_id);
     }
 
+    // Overrides the wire radius of a single net by giving it its own copy of
+    // its current ruleset.
+    pub fn set_net_radius(&mut self, net_id: Id, radius: f64) {
+        let mut rs = self.net_ruleset(net_id).clone();
+        rs.id = self.to_id(&format!("{}/radius", self.to_name(net_id)));
+        rs.radius = Some(radius);
+        self.set_net_ruleset(net_id, rs.id);
+        self.add_ruleset(rs);
+    }
+
     pub fn net_ruleset(&self, net_id: Id) -> &RuleSet {
         let ruleset_id = self.net_to_ruleset.get(&net_id).unwrap_or(&self.default_net_ruleset);
         self.rulesets.get(ruleset_id).unwrap()
     }
 
+    pub fn set_layer_radius(&mut self, layer: LayerId, radius: f64) {
+        self.layer_radius.insert(layer, radius);
+    }
+
+    pub fn set_default_radius(&mut self, radius: f64) {
+        self.default_radius = Some(radius);
+    }
+
+    pub fn set_wire_grid(&mut self, grid: f64) {
+        self.wire_grid = Some(grid);
+    }
+
+    // Spacing of the routing grid declared by the design, if any.
+    #[must_use]
+    pub fn wire_grid(&self) -> Option<f64> {
+        self.wire_grid
+    }
+
+    // Rules for the given net, with anything its ruleset doesn't specify taken
+    // from the default ruleset and the structure. Clearances from both are kept.
+    pub fn resolved_rules(&self, net_id: Id) -> ResolvedRules {
+        let rs = self.net_ruleset(net_id);
+        let default = self.rulesets.get(&self.default_net_ruleset).unwrap_or(rs);
+        let mut clearances = rs.clearances().to_vec();
+        for c in default.clearances() {
+            if !clearances.contains(c) {
+                clearances.push(*c);
+            }
+        }
+        let allowed_layers = rs
+            .allowed_layers()
+            .or(default.allowed_layers())
+            .unwrap_or_else(|| self.copper_layers());
+        let radius = allowed_layers
+            .iter()
+            .filter_map(|l| Some((l, self.resolve_radius(net_id, l)?)))
+            .collect();
+        let use_via = rs.use_via().or(default.use_via());
+        ResolvedRules { radius, clearances, use_via, allowed_layers }
+    }
+
+    // Radius of wires for the given net on the given layer, if any width rule
+    // applies. See |net_radius| for the precedence.
+    fn resolve_radius(&self, net_id: Id, layer: LayerId) -> Option<f64> {
+        let class = self.net_to_ruleset.get(&net_id).and_then(|v| self.rulesets.get(v));
+        let default = self.rulesets.get(&self.default_net_ruleset);
+        class
+            .and_then(RuleSet::radius)
+            .or_else(|| self.layer_radius.get(&layer).copied())
+            .or_else(|| default.and_then(RuleSet::radius))
+            .or(self.default_radius)
+    }
+
+    // Radius of wires for the given net on the given layer. In order of precedence,
+    // this comes from the net's class, then the layer, then the default class,
+    // then the structure default. Fails if none of them has a width rule.
+    pub fn net_radius(&self, net_id: Id, layer: LayerId) -> Result<f64> {
+        self.resolve_radius(net_id, layer)
+            .ok_or_else(|| eyre!("no wire width for net {} on layer {}", net_id, layer))
+    }
+
     pub fn add_layer(&mut self, l: Layer) {
         self.layers.push(l);
     }
@@ -605,6 +1354,14 @@ impl Pcb {
         &self.keepouts
     }
 
+    pub fn add_plane(&mut self, p: Plane) {
+        self.planes.push(p);
+    }
+
+    pub fn planes(&self) -> &[Plane] {
+        &self.planes
+    }
+
     pub fn add_via_padstack(&mut self, p: Padstack) {
         self.via_padstacks.push(p);
     }
@@ -626,6 +1383,7 @@ impl Pcb {
     }
 
     pub fn add_wire(&mut self, w: Wire) {
+        self.net_wires.entry(w.net_id).or_default().push(self.wires.len());
         self.wires.push(w);
     }
 
@@ -634,6 +1392,7 @@ impl Pcb {
     }
 
     pub fn add_via(&mut self, v: Via) {
+        self.net_vias.entry(v.net_id).or_default().push(self.vias.len());
         self.vias.push(v);
     }
 
@@ -641,6 +1400,29 @@ impl Pcb {
         &self.vias
     }
 
+    // Removes all wires and vias in the given net.
+    pub fn remove_net_routing(&mut self, net_id: Id) {
+        self.wires.retain(|v| v.net_id != net_id);
+        self.vias.retain(|v| v.net_id != net_id);
+        // Indices into |wires| and |vias| have changed, so rebuild them.
+        self.net_wires.clear();
+        for (idx, v) in self.wires.iter().enumerate() {
+            self.net_wires.entry(v.net_id).or_default().push(idx);
+        }
+        self.net_vias.clear();
+        for (idx, v) in self.vias.iter().enumerate() {
+            self.net_vias.entry(v.net_id).or_default().push(idx);
+        }
+    }
+
+    pub fn net_wires(&self, id: Id) -> impl Iterator<Item = &Wire> {
+        self.net_wires.get(&id).into_iter().flatten().map(|&idx| &self.wires[idx])
+    }
+
+    pub fn net_vias(&self, id: Id) -> impl Iterator<Item = &Via> {
+        self.net_vias.get(&id).into_iter().flatten().map(|&idx| &self.vias[idx])
+    }
+
     pub fn add_net(&mut self, n: Net) {
         for p in &n.pins {
             self.pin_ref_to_net.insert(p.clone(), n.id);
@@ -663,4 +1445,227 @@ impl Pcb {
     pub fn debug_rts(&self) -> &[Rt] {
         &self.debug_rts
     }
+
+    pub fn add_diff_pair(&mut self, a: Id, b: Id) {
+        self.diff_pairs.push((a, b));
+    }
+
+    pub fn diff_pairs(&self) -> &[(Id, Id)] {
+        &self.diff_pairs
+    }
+
+    // Returns the other net in the differential pair the given net is in, if any.
+    #[must_use]
+    pub fn diff_pair_partner(&self, net_id: Id) -> Option<Id> {
+        self.diff_pairs.iter().find_map(|&(a, b)| {
+            if a == net_id {
+                Some(b)
+            } else if b == net_id {
+                Some(a)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn add_diagnostic(&mut self, d: String) {
+        self.diagnostics.push(d);
+    }
+
+    #[must_use]
+    pub fn diagnostics(&self) -> &[String] {
+        &self.diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memegeom::geom::math::pt_eq;
+    use memegeom::primitive::{pt, rt};
+
+    use super::*;
+    use crate::dsn::load_dsn;
+    use crate::testing::{test_pcb, TEST_DSN};
+
+    #[test]
+    fn net_radius_precedence() {
+        let mut pcb = test_pcb();
+        let (a, b) = (pcb.to_id("A"), pcb.to_id("B"));
+        pcb.set_layer_radius(1, 0.3);
+        pcb.set_default_radius(0.05);
+        // Net A's class beats the layer width.
+        assert!(eq(pcb.net_radius(a, 1).unwrap(), 0.2));
+        // Net B has no class of its own, so the layer beats the default class.
+        assert!(eq(pcb.net_radius(b, 1).unwrap(), 0.3));
+        assert!(eq(pcb.net_radius(b, 0).unwrap(), 0.1));
+        // Without a width in the default class, the structure width is used.
+        let id = pcb.to_id("no_width");
+        pcb.add_ruleset(RuleSet::new(id, vec![]).unwrap());
+        pcb.set_default_net_ruleset(id);
+        assert!(eq(pcb.net_radius(b, 0).unwrap(), 0.05));
+        // No width at all is an error rather than a panic.
+        assert!(Pcb::default().net_radius(0, 0).is_err());
+    }
+
+    #[test]
+    fn snap_placements_moves_pins() {
+        let mut pcb = test_pcb();
+        let id = pcb.to_id("R2");
+        let mut c = pcb.component(id).unwrap().clone();
+        c.p = pt(1.03, 2.07);
+        pcb.add_component(c);
+        assert!(pcb.snap_placements(0.0).is_err());
+        assert!(pcb.snap_placements(-0.5).is_err());
+        pcb.snap_placements(0.5).unwrap();
+
+        let c = pcb.component(id).unwrap();
+        assert!(pt_eq(c.p, pt(1.0, 2.0)), "{:?}", c.p);
+        // R2's pin is 1 mm to the right of it.
+        let pin = c.pin(pcb.to_id("1")).unwrap();
+        let p = (c.tf() * pin.tf()).pt(Pt::zero());
+        assert!(pt_eq(p, pt(2.0, 2.0)), "{p:?}");
+    }
+
+    #[test]
+    fn copper_ratio_error_is_bounded() {
+        let mut pcb = test_pcb();
+        // A plane over the left half of the 20x20 board, which has R1 and R3's
+        // pads in it. R2 and R4's pads are outside it.
+        let shape = rt(0.0, 0.0, 10.0, 20.0).shape();
+        let shape = LayerShape { layers: LayerSet::one(0), shape };
+        pcb.add_plane(Plane { net_id: pcb.to_id("A"), shape, windows: Vec::new() });
+        let pad_area = std::f64::consts::PI * 0.5 * 0.5;
+        let expected = (200.0 + 2.0 * pad_area) / 400.0;
+        // The plane edges lie between samples, so only the pads' edges count.
+        let edges = 2.0 * std::f64::consts::PI * 0.5 * 2.0;
+        let cell = 20.0 / 200.0 * 2.0_f64.sqrt();
+        let ratio = pcb.copper_ratio(0);
+        assert!((ratio - expected).abs() <= edges * cell / 400.0, "{ratio} != {expected}");
+        // Nothing is on the back layer.
+        assert!(eq(pcb.copper_ratio(1), 0.0));
+    }
+
+    #[test]
+    fn merge_with_itself() {
+        let mut pcb = test_pcb();
+        let mut other = test_pcb();
+        other.add_diff_pair(other.to_id("A"), other.to_id("B"));
+        other.set_layer_radius(0, 0.3);
+        pcb.merge(&other, pt(20.0, 0.0)).unwrap();
+
+        assert_eq!(pcb.components().count(), 8);
+        assert_eq!(pcb.nets().count(), 4);
+        let ids: HashSet<_> = pcb.components().map(|c| c.id).collect();
+        assert_eq!(ids.len(), 8);
+        let c = pcb.component(pcb.to_id("1/R1")).unwrap();
+        assert!(eq(c.p.x, 25.0) && eq(c.p.y, 10.0), "{:?}", c.p);
+        let net_id = pcb.to_id("1/A");
+        let net = pcb.net(net_id).unwrap();
+        assert!(net.pins.iter().all(|p| pcb.to_name(p.component).starts_with("1/")));
+        assert_eq!(pcb.net_ruleset(net_id).id, pcb.to_id("wide"));
+        assert_eq!(pcb.diff_pairs(), [(net_id, pcb.to_id("1/B"))]);
+        assert!(pcb.layer_radius.get(&0).is_some_and(|&r| eq(r, 0.3)));
+
+        // Merging again uses a new namespace.
+        pcb.merge(&test_pcb(), pt(40.0, 0.0)).unwrap();
+        assert_eq!(pcb.components().count(), 12);
+        assert!(pcb.component(pcb.to_id("2/R1")).is_some());
+    }
+
+    #[test]
+    fn merge_conflict_leaves_pcb_unchanged() {
+        let mut pcb = test_pcb();
+        pcb.set_layer_radius(0, 0.5);
+        let mut other = test_pcb();
+        other.set_layer_radius(0, 0.3);
+        assert!(pcb.merge(&other, pt(20.0, 0.0)).is_err());
+        assert_eq!(pcb.components().count(), 4);
+        assert_eq!(pcb.nets().count(), 2);
+        assert_eq!(pcb.boundaries().len(), 1);
+        // No names were added.
+        assert_eq!(pcb.name_map.read().unwrap().find("1/R1"), None);
+
+        // Via padstacks with the same name must have the same shapes.
+        let mut other = test_pcb();
+        let via = other.via_padstacks[0].clone();
+        other.via_padstacks[0] = Padstack { shapes: vec![via.shapes[0].clone()], ..via };
+        assert!(pcb.merge(&other, pt(20.0, 0.0)).is_err());
+        assert_eq!(pcb.components().count(), 4);
+    }
+
+    #[test]
+    fn pick_and_place_flipped_rotated() {
+        let dsn =
+            TEST_DSN.replace("(place R2 15000 10000 front 0)", "(place R2 15000 10000 back 90)");
+        let pcb = load_dsn(&dsn).unwrap();
+        let rows = pcb.pick_and_place();
+        let names: Vec<_> = rows.iter().map(|v| v.refdes.as_str()).collect();
+        assert_eq!(names, ["R1", "R2", "R3", "R4"]);
+        let r2 = &rows[1];
+        assert_eq!(r2.footprint, "pad_image");
+        assert!(eq(r2.x, 15.0) && eq(r2.y, 10.0), "{r2:?}");
+        assert!(eq(r2.rotation, 90.0), "{r2:?}");
+        assert!(r2.flipped);
+        assert!(!rows[0].flipped);
+        let d = pcb.component(pcb.to_id("R2")).unwrap().designator.unwrap();
+        assert!(pt_eq(d.p, pt(15.0, 10.0)) && eq(d.rotation, 90.0), "{d:?}");
+    }
+
+    #[test]
+    fn content_bounds_without_boundary() {
+        let dsn = TEST_DSN.replace("(boundary (rect pcb 0 0 20000 20000))", "");
+        let pcb = load_dsn(&dsn).unwrap();
+        assert!(pcb.boundaries().is_empty());
+        // Pads have radius 0.5, at (5, 11), (16, 10), (6, 5) and (16, 5).
+        let b = pcb.content_bounds();
+        assert!(pt_eq(b.bl(), pt(4.5, 4.5)) && pt_eq(b.tr(), pt(16.5, 11.5)), "{b:?}");
+    }
+
+    #[test]
+    fn expand_via_array_2x3() {
+        let pcb = test_pcb();
+        let net_id = pcb.to_id("A");
+        let template = Via { padstack: pcb.via_padstacks()[0].clone(), p: pt(1.0, 2.0), net_id };
+        let vias = expand_via_array(&template, 2, 3, 0.5);
+        let pts: Vec<Pt> = vias.iter().map(|v| v.p).collect();
+        let expected = [(1.0, 2.0), (1.5, 2.0), (2.0, 2.0), (1.0, 2.5), (1.5, 2.5), (2.0, 2.5)];
+        assert_eq!(pts.len(), expected.len());
+        for (&p, &(x, y)) in pts.iter().zip(&expected) {
+            assert!(pt_eq(p, pt(x, y)), "{pts:?}");
+        }
+        assert!(vias.iter().all(|v| v.net_id == net_id && v.padstack.id == template.padstack.id));
+    }
+
+    #[test]
+    fn net_mst_length_right_triangle() {
+        let dsn = TEST_DSN
+            .replace("(net A (pins R1-1 R2-1))", "(net A (pins R1-1))")
+            .replace("(net B (pins R3-1 R4-1))", "(net B (pins R2-1 R3-1 R4-1))");
+        let pcb = load_dsn(&dsn).unwrap();
+        // R2, R3 and R4's pads are at (16, 10), (6, 5) and (16, 5), so the right
+        // angle is at R4 and the shorter edges are 5 and 10 long.
+        assert!(eq(pcb.net_mst_length(pcb.to_id("B")), 15.0));
+        assert!(eq(pcb.net_mst_length(pcb.to_id("A")), 0.0));
+    }
+
+    #[test]
+    fn normalize_origin_moves_everything() {
+        let dsn =
+            TEST_DSN.replace("(rect pcb 0 0 20000 20000)", "(rect pcb 1000 2000 20000 20000)");
+        let mut pcb = load_dsn(&dsn).unwrap();
+        let net_id = pcb.to_id("A");
+        let shape = path(&[pt(3.0, 3.0), pt(4.0, 3.0)], 0.1).shape();
+        pcb.add_wire(Wire { shape: LayerShape { layers: LayerSet::one(0), shape }, net_id });
+        let padstack = pcb.via_padstacks()[0].clone();
+        pcb.add_via(Via { padstack, p: pt(4.0, 3.0), net_id });
+
+        pcb.normalize_origin();
+        assert!(pt_eq(pcb.bounds().bl(), Pt::zero()), "{:?}", pcb.bounds());
+        assert!(pt_eq(pcb.bounds().tr(), pt(19.0, 18.0)), "{:?}", pcb.bounds());
+        let r1 = pcb.component(pcb.to_id("R1")).unwrap();
+        assert!(pt_eq(r1.p, pt(4.0, 8.0)), "{:?}", r1.p);
+        let Shape::Path(p) = &pcb.wires()[0].shape.shape else { panic!("wire is not a path") };
+        assert!(pt_eq(p.pts()[0], pt(2.0, 1.0)) && pt_eq(p.pts()[1], pt(3.0, 1.0)), "{p:?}");
+        assert!(pt_eq(pcb.vias()[0].p, pt(3.0, 1.0)));
+    }
 }
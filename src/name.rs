@@ -19,6 +19,12 @@ impl NameMap {
         self.id_to_name.get(&id).unwrap()
     }
 
+    // Returns the ID of |name| without adding it if it's missing.
+    #[must_use]
+    pub fn find(&self, name: &str) -> Option<Id> {
+        self.name_to_id.get(name).copied()
+    }
+
     pub fn name_to_id(&mut self, name: &str) -> Id {
         if let Some(id) = self.name_to_id.get(name) {
             *id
@@ -0,0 +1,145 @@
+use ahash::{HashMap, HashSet};
+use eyre::{eyre, Result};
+use memegeom::primitive::point::Pt;
+use memegeom::primitive::shape::Shape;
+use memegeom::primitive::{circ, pt, ShapeOps};
+
+use crate::model::pcb::Pcb;
+use crate::name::Id;
+
+// Points closer than this (in mm) are considered the same node.
+const NODE_EPSILON: f64 = 1e-6;
+
+type NodeKey = (i64, i64);
+
+fn node_key(p: Pt) -> NodeKey {
+    ((p.x / NODE_EPSILON).round() as i64, (p.y / NODE_EPSILON).round() as i64)
+}
+
+// Returns the parameter along segment |a|-|b| at which |p| lies, if |p| is
+// strictly inside the segment.
+fn interior_t(a: Pt, b: Pt, p: Pt) -> Option<f64> {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let l2 = dx * dx + dy * dy;
+    if l2 == 0.0 {
+        return None;
+    }
+    let t = ((p.x - a.x) * dx + (p.y - a.y) * dy) / l2;
+    let q = pt(a.x + t * dx, a.y + t * dy);
+    let inside = t * t * l2 > NODE_EPSILON * NODE_EPSILON
+        && (1.0 - t) * (1.0 - t) * l2 > NODE_EPSILON * NODE_EPSILON;
+    (inside && q.dist(p) < NODE_EPSILON).then_some(t)
+}
+
+// Returns the lengths of dead-end branches (stubs) of the given net's wires.
+// A stub runs from a wire end which doesn't touch a pin back to the nearest
+// junction or pin.
+pub fn stubs(pcb: &Pcb, net_id: Id) -> Result<Vec<f64>> {
+    let net = pcb.net(net_id).ok_or_else(|| eyre!("missing net {}", net_id))?;
+
+    // Collect the wire segments. Layers are ignored, since vias connect them
+    // at the same point.
+    let mut segs: Vec<(Pt, Pt)> = Vec::new();
+    for wire in pcb.net_wires(net_id) {
+        let Shape::Path(s) = &wire.shape.shape else { continue };
+        segs.extend(s.pts().windows(2).map(|v| (v[0], v[1])));
+    }
+
+    // Build the connectivity graph. A segment end which lies in the middle of
+    // another segment (a T-junction) splits that segment there.
+    let ends: Vec<Pt> = segs.iter().flat_map(|&(a, b)| [a, b]).collect();
+    let mut pts: HashMap<NodeKey, Pt> = HashMap::default();
+    let mut adj: HashMap<NodeKey, Vec<(NodeKey, f64)>> = HashMap::default();
+    for &(a, b) in &segs {
+        let mut split = vec![(0.0, a), (1.0, b)];
+        split.extend(ends.iter().filter_map(|&p| interior_t(a, b, p).map(|t| (t, p))));
+        split.sort_by(|x, y| x.0.total_cmp(&y.0));
+        for v in split.windows(2) {
+            let (ka, kb) = (node_key(v[0].1), node_key(v[1].1));
+            if ka == kb {
+                continue;
+            }
+            let d = v[0].1.dist(v[1].1);
+            pts.insert(ka, v[0].1);
+            pts.insert(kb, v[1].1);
+            adj.entry(ka).or_default().push((kb, d));
+            adj.entry(kb).or_default().push((ka, d));
+        }
+    }
+
+    let mut pin_shapes = Vec::new();
+    for p in &net.pins {
+        let (component, pin) = pcb.pin_ref(p)?;
+        let tf = component.tf() * pin.tf();
+        pin_shapes.extend(pin.padstack.shapes.iter().map(|s| tf.shape(&s.shape)));
+    }
+    let is_pin = |k: &NodeKey| {
+        let p = circ(pts[k], 0.0).shape();
+        pin_shapes.iter().any(|s| s.intersects_shape(&p))
+    };
+
+    let mut res = Vec::new();
+    // Leaves already reached from the other end of a branch which has no pin
+    // or junction at all, so it isn't reported twice.
+    let mut seen: HashSet<NodeKey> = HashSet::default();
+    for (&leaf, edges) in &adj {
+        if edges.len() != 1 || is_pin(&leaf) || seen.contains(&leaf) {
+            continue;
+        }
+        // Walk back along the branch until we hit a junction or a pin.
+        let mut len = 0.0;
+        let (mut prev, mut cur) = (leaf, leaf);
+        loop {
+            let next = adj[&cur].iter().find(|&&(k, _)| k != prev || cur == leaf);
+            let Some(&(next, d)) = next else { break };
+            len += d;
+            (prev, cur) = (cur, next);
+            if adj[&cur].len() != 2 || is_pin(&cur) {
+                break;
+            }
+        }
+        if adj[&cur].len() == 1 && !is_pin(&cur) {
+            seen.insert(cur);
+        }
+        res.push(len);
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use memegeom::geom::math::eq;
+    use memegeom::primitive::path;
+
+    use super::*;
+    use crate::model::pcb::{LayerSet, LayerShape, Wire};
+    use crate::testing::test_pcb;
+
+    fn add_wire(pcb: &mut Pcb, net_id: Id, pts: &[Pt]) {
+        let shape = path(pts, 0.1).shape();
+        pcb.add_wire(Wire { shape: LayerShape { layers: LayerSet::one(0), shape }, net_id });
+    }
+
+    #[test]
+    fn stubs_t_junction() {
+        let mut pcb = test_pcb();
+        let b = pcb.to_id("B");
+        // Net B connects R3 at (6, 5) and R4 at (16, 5). The branch leaves the
+        // middle of that wire and goes nowhere.
+        add_wire(&mut pcb, b, &[pt(6.0, 5.0), pt(16.0, 5.0)]);
+        add_wire(&mut pcb, b, &[pt(10.0, 5.0), pt(10.0, 7.0)]);
+        let stubs = stubs(&pcb, b).unwrap();
+        assert_eq!(stubs.len(), 1, "{stubs:?}");
+        assert!(eq(stubs[0], 2.0), "{stubs:?}");
+    }
+
+    #[test]
+    fn stubs_floating_wire_reported_once() {
+        let mut pcb = test_pcb();
+        let b = pcb.to_id("B");
+        add_wire(&mut pcb, b, &[pt(1.0, 1.0), pt(3.0, 1.0), pt(3.0, 2.0)]);
+        let stubs = stubs(&pcb, b).unwrap();
+        assert_eq!(stubs.len(), 1, "{stubs:?}");
+        assert!(eq(stubs[0], 3.0), "{stubs:?}");
+    }
+}
@@ -0,0 +1,148 @@
+use std::f64::consts::PI;
+
+use memegeom::geom::math::eq;
+use memegeom::primitive::point::Pt;
+use memegeom::primitive::shape::Shape;
+use memegeom::primitive::{path, pt, ShapeOps};
+
+use crate::model::pcb::Wire;
+use crate::route::place_model::PlaceModel;
+use crate::route::router::RouteResult;
+
+const ARC_STEP: f64 = PI / 16.0; // Maximum angle between points on an arc.
+
+// Replaces corners in the routed wires with tangent arcs of the given radius.
+// Arcs are tessellated into the wire's path. A corner is left as is if its
+// segments are too short to fit the arc, or if the arc would be blocked in
+// |place|.
+pub fn round_corners(place: &PlaceModel, res: &mut RouteResult, radius: f64) {
+    for wire in &mut res.wires {
+        round_wire(place, wire, radius);
+    }
+}
+
+fn round_wire(place: &PlaceModel, wire: &mut Wire, radius: f64) {
+    let Shape::Path(p) = &wire.shape.shape else { return };
+    let Some(layer) = wire.shape.layers.id() else { return };
+    let r = p.r();
+    let pts = corners(p.pts());
+    if pts.len() < 3 {
+        return;
+    }
+
+    let mut out = vec![pts[0]];
+    for v in pts.windows(3) {
+        let (a, b, c) = (v[0], v[1], v[2]);
+        let arc = corner_arc(a, b, c, radius).filter(|arc| {
+            let arc_wire = place.create_wire_radius(wire.net_id, layer, arc, r);
+            !place.is_wire_blocked(&arc_wire)
+        });
+        match arc {
+            Some(arc) => out.extend(arc),
+            None => out.push(b),
+        }
+    }
+    out.push(pts[pts.len() - 1]);
+    wire.shape.shape = path(&out, r).shape();
+}
+
+// Drops points in the middle of straight runs, so each remaining interior
+// point is a corner. Grid routed paths have a point per grid square.
+pub(crate) fn corners(pts: &[Pt]) -> Vec<Pt> {
+    let mut out: Vec<Pt> = Vec::with_capacity(pts.len());
+    for &p in pts {
+        if let [.., a, b] = out[..] {
+            let cross = (b.x - a.x) * (p.y - b.y) - (b.y - a.y) * (p.x - b.x);
+            let dot = (b.x - a.x) * (p.x - b.x) + (b.y - a.y) * (p.y - b.y);
+            if eq(cross, 0.0) && dot >= 0.0 {
+                out.pop();
+            }
+        }
+        out.push(p);
+    }
+    out
+}
+
+// Returns points along the arc of radius |radius| tangent to both segments
+// |a|-|b| and |b|-|c|, or None if there is no corner or the arc doesn't fit.
+// Each arc uses at most half of each segment so arcs on adjacent corners
+// don't overlap.
+fn corner_arc(a: Pt, b: Pt, c: Pt, radius: f64) -> Option<Vec<Pt>> {
+    let (lab, lbc) = (a.dist(b), b.dist(c));
+    if eq(lab, 0.0) || eq(lbc, 0.0) {
+        return None;
+    }
+    let u = (1.0 / lab) * (a - b);
+    let v = (1.0 / lbc) * (c - b);
+    // |theta| is the interior angle of the corner; straight runs have theta = pi.
+    let theta = (u.x * v.x + u.y * v.y).clamp(-1.0, 1.0).acos();
+    if eq(theta, PI) || eq(theta, 0.0) {
+        return None;
+    }
+    let d = radius / (theta / 2.0).tan();
+    if d > lab / 2.0 || d > lbc / 2.0 {
+        return None;
+    }
+    let (t0, t1) = (b + d * u, b + d * v);
+    let w = u + v;
+    let center = b + (radius / (theta / 2.0).sin() / w.dist(pt(0.0, 0.0))) * w;
+
+    let a0 = (t0.y - center.y).atan2(t0.x - center.x);
+    let a1 = (t1.y - center.y).atan2(t1.x - center.x);
+    // Go the short way around, which is the side facing the corner.
+    let sweep = (a1 - a0 + PI).rem_euclid(2.0 * PI) - PI;
+    let n = (sweep.abs() / ARC_STEP).ceil().max(1.0) as usize;
+    Some(
+        (0..=n)
+            .map(|i| {
+                let ang = a0 + sweep * i as f64 / n as f64;
+                pt(center.x + radius * ang.cos(), center.y + radius * ang.sin())
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use memegeom::geom::math::pt_eq;
+    use memegeom::primitive::rt;
+
+    use super::*;
+    use crate::model::pcb::{Keepout, KeepoutType, LayerSet, LayerShape, Pcb};
+    use crate::testing::test_pcb;
+
+    // Rounds a right angle corner at (12, 2) with radius 1 on |pcb|, and
+    // returns the resulting path.
+    fn round_right_angle(pcb: Pcb) -> Vec<Pt> {
+        let net_id = pcb.to_id("B");
+        let place = PlaceModel::new(pcb);
+        let wire = place.create_wire(net_id, 0, &[pt(8.0, 2.0), pt(12.0, 2.0), pt(12.0, 6.0)]);
+        let mut res = RouteResult { wires: vec![wire.unwrap()], ..Default::default() };
+        round_corners(&place, &mut res, 1.0);
+        let Shape::Path(p) = &res.wires[0].shape.shape else { panic!("wire is not a path") };
+        p.pts().to_vec()
+    }
+
+    #[test]
+    fn right_angle_becomes_arc() {
+        let pts = round_right_angle(test_pcb());
+        assert!(pts.len() > 4, "{pts:?}");
+        assert!(!pts.iter().any(|&v| pt_eq(v, pt(12.0, 2.0))), "{pts:?}");
+        // The arc is centered on (11, 3).
+        for &v in &pts[1..pts.len() - 1] {
+            assert!(eq(v.dist(pt(11.0, 3.0)), 1.0), "{v:?}");
+        }
+    }
+
+    #[test]
+    fn blocked_arc_keeps_corner() {
+        let mut pcb = test_pcb();
+        // The middle of the arc is at about (11.71, 2.29).
+        let shape = rt(11.69, 2.27, 11.73, 2.31).shape();
+        let shape = LayerShape { layers: LayerSet::one(0), shape };
+        pcb.add_keepout(Keepout { kind: KeepoutType::Keepout, shape, windows: Vec::new() });
+        let pts = round_right_angle(pcb);
+        assert_eq!(pts.len(), 3, "{pts:?}");
+        assert!(pt_eq(pts[1], pt(12.0, 2.0)), "{pts:?}");
+    }
+}
@@ -0,0 +1,384 @@
+use ahash::HashSet;
+use eyre::Result;
+use memegeom::geom::math::le;
+use memegeom::geom::qt::query::{Tag, TagQuery};
+use memegeom::primitive::point::Pt;
+use memegeom::primitive::{circ, ShapeOps};
+use memegeom::tf::Tf;
+use serde::Serialize;
+
+use crate::model::pcb::{Clearance, LayerId, LayerSet, ObjectKind, Pcb, PinRef};
+use crate::name::{Id, NO_ID};
+use crate::route::place_model::{PlaceId, PlaceModel};
+use crate::route::router::{apply_route_result, RouteResult};
+
+// A via which passes through a plane of another net without enough clearance.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AntipadViolation {
+    pub via_idx: usize, // Index into Pcb::vias.
+    pub via_net: Id,
+    pub plane_net: Id,
+    pub layer: LayerId,
+}
+
+// Checks that every via passing through a plane of a different net has at
+// least |antipad| clearance between its barrel and the plane copper.
+pub fn antipad_violations(pcb: &Pcb, antipad: f64) -> Vec<AntipadViolation> {
+    let mut violations = Vec::new();
+    for (via_idx, via) in pcb.vias().iter().enumerate() {
+        let tf = via.tf();
+        for plane in pcb.planes() {
+            if plane.net_id == via.net_id {
+                continue;
+            }
+            for s in &via.padstack.shapes {
+                for layer in (s.layers & plane.shape.layers).iter() {
+                    // Inflate the via by the antipad, then it must fit inside a window.
+                    let b = tf.shape(&s.shape).bounds();
+                    let r = (b.r() - b.l()).max(b.t() - b.b()) / 2.0;
+                    let keepout = circ(via.p, r + antipad).shape();
+                    if plane.shape.shape.intersects_shape(&keepout)
+                        && !plane.windows.iter().any(|w| w.contains_shape(&keepout))
+                    {
+                        violations.push(AntipadViolation {
+                            via_idx,
+                            via_net: via.net_id,
+                            plane_net: plane.net_id,
+                            layer,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    violations
+}
+
+// An object on the board which is part of a violation.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DrcObject {
+    Wire(usize), // Index into Pcb::wires.
+    Via(usize),  // Index into Pcb::vias.
+    Pin(PinRef),
+}
+
+impl DrcObject {
+    pub fn kind(&self) -> ObjectKind {
+        match self {
+            DrcObject::Wire(_) => ObjectKind::Wire,
+            DrcObject::Via(_) => ObjectKind::Via,
+            DrcObject::Pin(_) => ObjectKind::Pin,
+        }
+    }
+}
+
+// A wire or via which is too close to an object of another net, or which
+// leaves the board or overlaps a keepout. |measured| is the distance to
+// |other| and |required| is the clearance rule between the two which is
+// broken. |other| is None for boundary and keepout violations, in which case
+// both distances are zero.
+#[must_use]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub object: DrcObject, // Either a wire or a via.
+    pub net_id: Id,
+    pub other: Option<DrcObject>,
+    pub layers: LayerSet,
+    pub measured: f64,
+    pub required: f64,
+}
+
+// An object placed for checking, along with the place ids of its shapes.
+struct Placed {
+    object: DrcObject,
+    net_id: Id,
+    ids: Vec<PlaceId>,
+}
+
+// Checks the existing wires and vias of |pcb| against its clearance rules,
+// without routing anything. Useful for verifying boards routed elsewhere.
+#[must_use]
+pub fn check_drc(pcb: &Pcb) -> Vec<Violation> {
+    let mut place = PlaceModel::new(pcb.clone());
+    // Place the routing again, so the place ids of each wire and via are known.
+    let nets: HashSet<Id> =
+        pcb.wires().iter().map(|v| v.net_id).chain(pcb.vias().iter().map(|v| v.net_id)).collect();
+    for net_id in nets {
+        place.remove_net_routing(net_id);
+    }
+    let mut placed = Vec::new();
+    for (idx, wire) in pcb.wires().iter().enumerate() {
+        let ids = place.add_wire(wire);
+        placed.push(Placed { object: DrcObject::Wire(idx), net_id: wire.net_id, ids });
+    }
+    for (idx, via) in pcb.vias().iter().enumerate() {
+        let ids = place.add_via(via);
+        placed.push(Placed { object: DrcObject::Via(idx), net_id: via.net_id, ids });
+    }
+    for c in pcb.components() {
+        for pin in c.pins() {
+            let r = PinRef::new(c, pin);
+            let net_id = pcb.pin_ref_net(&r).unwrap_or(NO_ID);
+            let ids = place.pin_place_ids(&r).to_vec();
+            placed.push(Placed { object: DrcObject::Pin(r), net_id, ids });
+        }
+    }
+
+    let mut violations = Vec::new();
+    for p in &placed {
+        let clearances = pcb.resolved_rules(p.net_id).clearances;
+        let q = TagQuery::Except(Tag(p.net_id));
+        let (blocked, layers) = match p.object {
+            DrcObject::Wire(idx) => {
+                let wire = &pcb.wires()[idx];
+                let tf = Tf::identity();
+                let blocked =
+                    place.is_shape_blocked(&tf, &wire.shape, q, ObjectKind::Wire, &clearances);
+                (blocked, wire.shape.layers)
+            }
+            DrcObject::Via(idx) => {
+                let via = &pcb.vias()[idx];
+                let blocked = via.padstack.shapes.iter().any(|s| {
+                    place.is_shape_blocked(&via.tf(), s, q, ObjectKind::Via, &clearances)
+                });
+                (blocked, via.padstack.layers())
+            }
+            DrcObject::Pin(_) => continue,
+        };
+        if !blocked {
+            continue;
+        }
+        // If nothing of another net is too close, it must be outside the
+        // boundary or in a keepout.
+        let v = worst_violation(&mut place, &placed, p, &clearances).unwrap_or_else(|| Violation {
+            object: p.object.clone(),
+            net_id: p.net_id,
+            other: None,
+            layers,
+            measured: 0.0,
+            required: 0.0,
+        });
+        violations.push(v);
+    }
+    violations
+}
+
+// Finds the object of another net which |p| is furthest inside the clearance of.
+fn worst_violation(
+    place: &mut PlaceModel,
+    placed: &[Placed],
+    p: &Placed,
+    clearances: &[Clearance],
+) -> Option<Violation> {
+    let mut worst: Option<Violation> = None;
+    for other in placed.iter().filter(|v| v.net_id != p.net_id) {
+        let (a, b) = (p.object.kind(), other.object.kind());
+        let required = clearances
+            .iter()
+            .filter(|c| c.applies(a, b))
+            .map(Clearance::amount)
+            .fold(0.0, f64::max);
+        for &x in &p.ids {
+            for &y in other.ids.iter().filter(|y| y.0 == x.0) {
+                let measured = place.clearance_between(x, y);
+                let excess = required - measured;
+                if le(measured, required)
+                    && worst.as_ref().map_or(true, |w| w.required - w.measured < excess)
+                {
+                    worst = Some(Violation {
+                        object: p.object.clone(),
+                        net_id: p.net_id,
+                        other: Some(other.object.clone()),
+                        layers: LayerSet::one(x.0),
+                        measured,
+                        required,
+                    });
+                }
+            }
+        }
+    }
+    worst
+}
+
+// Checks the wires and vias of |res| once added to |pcb|. Indices in the
+// violations are into the wires and vias of |pcb| with |res| applied, i.e.
+// those of |pcb| followed by those of |res|.
+#[must_use]
+pub fn route_violations(pcb: &Pcb, res: &RouteResult) -> Vec<Violation> {
+    let mut board = pcb.clone();
+    apply_route_result(&mut board, res);
+    let (wires, vias) = (pcb.wires().len(), pcb.vias().len());
+    check_drc(&board)
+        .into_iter()
+        .filter(|v| match v.object {
+            DrcObject::Wire(idx) => idx >= wires,
+            DrcObject::Via(idx) => idx >= vias,
+            DrcObject::Pin(_) => false,
+        })
+        .collect()
+}
+
+// A violation with ids resolved to names, for consumption outside memeroute.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ViolationReport {
+    pub kind: String,
+    pub net: String,
+    pub other: Option<String>, // Object that is too close, if any.
+    pub other_net: Option<String>,
+    pub layers: Vec<String>,
+    pub x: f64, // Center of the offending wire or via.
+    pub y: f64,
+    pub measured_clearance: f64,
+    pub required_clearance: f64, // Amount of the clearance rule which is broken.
+}
+
+fn layer_names(pcb: &Pcb, layers: LayerSet) -> Vec<String> {
+    pcb.layers()
+        .iter()
+        .filter(|l| layers.contains(l.layer_id))
+        .map(|l| pcb.to_name(l.name_id))
+        .collect()
+}
+
+// Human readable name of an object, e.g. "wire 3" or "pin U1-2".
+fn object_name(pcb: &Pcb, o: &DrcObject) -> String {
+    match o {
+        DrcObject::Wire(idx) => format!("wire {idx}"),
+        DrcObject::Via(idx) => format!("via {idx}"),
+        DrcObject::Pin(r) => format!("pin {}-{}", pcb.to_name(r.component), pcb.to_name(r.pin)),
+    }
+}
+
+// Describes each violation found on |pcb|, e.g. by |check_drc|.
+pub fn violation_reports(pcb: &Pcb, violations: &[Violation]) -> Result<Vec<ViolationReport>> {
+    violations
+        .iter()
+        .map(|v| {
+            let p = match &v.object {
+                DrcObject::Via(idx) => pcb.vias()[*idx].p,
+                DrcObject::Wire(idx) => {
+                    let b = pcb.wires()[*idx].shape.shape.bounds();
+                    0.5 * (b.bl() + b.tr())
+                }
+                DrcObject::Pin(r) => {
+                    let (component, pin) = pcb.pin_ref(r)?;
+                    (component.tf() * pin.tf()).pt(Pt::zero())
+                }
+            };
+            let other_net = v.other.as_ref().and_then(|o| match o {
+                DrcObject::Wire(idx) => Some(pcb.wires()[*idx].net_id),
+                DrcObject::Via(idx) => Some(pcb.vias()[*idx].net_id),
+                DrcObject::Pin(r) => pcb.pin_ref_net(r),
+            });
+            Ok(ViolationReport {
+                kind: format!("{:?}", v.object.kind()).to_lowercase(),
+                net: pcb.to_name(v.net_id),
+                other: v.other.as_ref().map(|o| object_name(pcb, o)),
+                other_net: other_net.map(|id| pcb.to_name(id)),
+                layers: layer_names(pcb, v.layers),
+                x: p.x,
+                y: p.y,
+                measured_clearance: v.measured,
+                required_clearance: v.required,
+            })
+        })
+        .collect()
+}
+
+// Serializes violations found on |pcb| as a JSON array of |ViolationReport|.
+pub fn violations_json(pcb: &Pcb, violations: &[Violation]) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&violation_reports(pcb, violations)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use memegeom::geom::math::eq;
+    use memegeom::primitive::{path, pt, rt};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::model::pcb::{LayerShape, Plane, Via, Wire};
+    use crate::testing::test_pcb;
+
+    // Adds a wire of net B on the front layer between the given points.
+    fn add_wire_b(pcb: &mut Pcb, a: Pt, b: Pt) {
+        let shape = path(&[a, b], 0.1).shape();
+        let net_id = pcb.to_id("B");
+        pcb.add_wire(Wire { shape: LayerShape { layers: LayerSet::one(0), shape }, net_id });
+    }
+
+    #[test]
+    fn drc_clean_board() {
+        let mut pcb = test_pcb();
+        // R3's pad is at (6, 5) and R4's at (16, 5).
+        add_wire_b(&mut pcb, pt(6.0, 5.0), pt(16.0, 5.0));
+        assert!(check_drc(&pcb).is_empty());
+    }
+
+    #[test]
+    fn drc_wire_too_close_to_pin() {
+        let mut pcb = test_pcb();
+        // R2's pad has radius 0.5 at (16, 10), so this wire is 0.1 mm from it
+        // with a clearance of 0.2 mm.
+        add_wire_b(&mut pcb, pt(10.0, 9.3), pt(16.0, 9.3));
+        let violations = check_drc(&pcb);
+        assert_eq!(violations.len(), 1, "{violations:?}");
+        let v = &violations[0];
+        assert_eq!(v.object, DrcObject::Wire(0));
+        assert_eq!(v.net_id, pcb.to_id("B"));
+        assert!(matches!(&v.other, Some(DrcObject::Pin(r)) if r.component == pcb.to_id("R2")));
+        assert!(eq(v.measured, 0.1), "{v:?}");
+        assert!(eq(v.required, 0.2), "{v:?}");
+    }
+
+    #[test]
+    fn json_reports_pins() {
+        let mut pcb = test_pcb();
+        let (a, b) = (pcb.to_id("A"), pcb.to_id("B"));
+        let shape = path(&[pt(15.0, 10.0), pt(17.0, 10.0)], 0.1).shape();
+        pcb.add_wire(Wire { shape: LayerShape { layers: LayerSet::one(0), shape }, net_id: b });
+        let r = pcb.net(a).unwrap().pins.iter().find(|v| pcb.to_name(v.component) == "R2");
+        let v = Violation {
+            object: DrcObject::Pin(r.unwrap().clone()),
+            net_id: a,
+            other: Some(DrcObject::Wire(0)),
+            layers: LayerSet::one(0),
+            measured: 0.0,
+            required: 0.2,
+        };
+        let json: Value = serde_json::from_str(&violations_json(&pcb, &[v]).unwrap()).unwrap();
+        let v = &json[0];
+        assert_eq!(v["kind"], "pin");
+        assert_eq!(v["net"], "A");
+        assert_eq!(v["other"], "wire 0");
+        assert_eq!(v["other_net"], "B");
+        assert_eq!(v["layers"][0], "F.Cu");
+        assert!(v["x"].as_f64().is_some_and(|x| eq(x, 16.0)));
+        assert!(v["y"].as_f64().is_some_and(|y| eq(y, 10.0)));
+        assert!(v["required_clearance"].as_f64().is_some_and(|c| eq(c, 0.2)));
+    }
+
+    #[test]
+    fn antipad_missing_in_plane() {
+        let mut pcb = test_pcb();
+        let (a, b) = (pcb.to_id("A"), pcb.to_id("B"));
+        let shape = rt(0.0, 0.0, 20.0, 20.0).shape();
+        let shape = LayerShape { layers: pcb.copper_layers(), shape };
+        let windows = vec![circ(pt(10.0, 15.0), 0.6).shape()];
+        pcb.add_plane(Plane { net_id: b, shape, windows });
+        let padstack = pcb.via_padstacks()[0].clone();
+        // via600 has radius 0.3, so with a 0.2 antipad it fits in the window.
+        for (p, net_id) in [(pt(10.0, 15.0), a), (pt(5.0, 15.0), a), (pt(15.0, 15.0), b)] {
+            pcb.add_via(Via { padstack: padstack.clone(), p, net_id });
+        }
+        let violations = antipad_violations(&pcb, 0.2);
+        // The via with no window is too close to the plane on both layers.
+        assert_eq!(violations.len(), 2, "{violations:?}");
+        for (layer, v) in violations.iter().enumerate() {
+            assert_eq!(v, &AntipadViolation { via_idx: 1, via_net: a, plane_net: b, layer });
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::f64::consts::FRAC_PI_4;
+
+use eyre::{eyre, Result};
+use itertools::Itertools;
+use memegeom::geom::math::eq;
+use memegeom::primitive::point::Pt;
+use memegeom::primitive::pt;
+use ordered_float::OrderedFloat;
+
+use crate::model::pcb::{LayerSet, Pcb, PinRef};
+use crate::name::Id;
+use crate::route::place_model::PlaceModel;
+use crate::route::router::RouteResult;
+
+const MAX_STEPS: usize = 64; // Limit on how far a stub can go, in steps.
+
+// A pin to escape, in world space.
+struct EscapePin {
+    p: Pt,
+    layers: LayerSet,
+    net_id: Id,
+}
+
+// Rounds the direction of |d| to the nearest multiple of 45 degrees and
+// returns it as a unit vector.
+fn octilinear_dir(d: Pt) -> Pt {
+    if eq(d.x, 0.0) && eq(d.y, 0.0) {
+        return pt(1.0, 0.0);
+    }
+    let a = (d.y.atan2(d.x) / FRAC_PI_4).round() * FRAC_PI_4;
+    pt(a.cos(), a.sin())
+}
+
+// Routes each pin of a component outwards, away from the middle of the
+// component, until it is clear of the other pins. Pins which can't go
+// straight out on their own layer get a short diagonal stub to a via in
+// between pins instead (a dog-bone). Pins without a net are skipped, and
+// nets with a pin that can't be escaped are reported as failed.
+pub fn escape_pins(pcb: &Pcb, component_id: Id) -> Result<RouteResult> {
+    let component =
+        pcb.component(component_id).ok_or_else(|| eyre!("unknown component {}", component_id))?;
+    let tf = component.tf();
+    let mut pins: Vec<EscapePin> = component
+        .pins()
+        .filter_map(|pin| {
+            let net_id = pcb.pin_ref_net(&PinRef::new(component, pin))?;
+            let layers = pin.padstack.shapes.iter().map(|v| v.layers).collect::<LayerSet>()
+                & pcb.copper_layers();
+            Some(EscapePin { p: (tf * pin.tf()).pt(Pt::zero()), layers, net_id })
+        })
+        .collect();
+    if pins.is_empty() {
+        return Ok(RouteResult::default());
+    }
+
+    let n = pins.len() as f64;
+    let mid = pt(
+        pins.iter().map(|v| v.p.x).sum::<f64>() / n,
+        pins.iter().map(|v| v.p.y).sum::<f64>() / n,
+    );
+    // Escape outer pins first, so inner pins route in between their stubs.
+    pins.sort_by_key(|v| OrderedFloat(-v.p.dist(mid)));
+    // Radius of the pin grid, and the closest distance between two pins.
+    let extent = pins.iter().map(|v| v.p.dist(mid)).fold(0.0, f64::max);
+    let pitch = pins
+        .iter()
+        .tuple_combinations()
+        .map(|(a, b)| a.p.dist(b.p))
+        .filter(|&d| !eq(d, 0.0))
+        .min_by_key(|&d| OrderedFloat(d))
+        .unwrap_or(1.0); // Only one pin location, so any short stub will do.
+
+    let mut place = PlaceModel::new(pcb.clone());
+    let mut res = RouteResult::default();
+    for pin in &pins {
+        let dir = octilinear_dir(pin.p - mid);
+        let mut escaped = false;
+        for layer in pin.layers.iter() {
+            // Step outwards half a pitch at a time until past the outermost pins.
+            let step = pitch / 2.0;
+            let end = (1..=MAX_STEPS)
+                .map(|i| pin.p + (i as f64 * step) * dir)
+                .find(|v| v.dist(mid) > extent + step);
+            let Some(end) = end else { continue };
+            let wire = place.create_wire(pin.net_id, layer, &[pin.p, end])?;
+            if !place.is_wire_blocked(&wire) {
+                place.add_wire(&wire);
+                res.wires.push(wire);
+                escaped = true;
+                break;
+            }
+        }
+        if !escaped {
+            escaped = dog_bone(&mut place, &mut res, pin, dir, pitch)?;
+        }
+        if !escaped {
+            res.failed = true;
+            if !res.failed_nets.contains(&pin.net_id) {
+                res.failed_nets.push(pin.net_id);
+            }
+        }
+    }
+    Ok(res)
+}
+
+// Tries a via in the middle of the square of pins diagonally outwards from
+// |pin|, connected to the pin with a short stub.
+fn dog_bone(
+    place: &mut PlaceModel,
+    res: &mut RouteResult,
+    pin: &EscapePin,
+    dir: Pt,
+    pitch: f64,
+) -> Result<bool> {
+    // Diagonal directions either side of |dir|, or |dir| itself if it's diagonal.
+    let a = dir.y.atan2(dir.x);
+    let diagonals = if eq((a / FRAC_PI_4).round() % 2.0, 0.0) {
+        vec![a - FRAC_PI_4, a + FRAC_PI_4]
+    } else {
+        vec![a]
+    };
+    for a in diagonals {
+        let q = pin.p + (pitch / 2.0 * 2.0_f64.sqrt()) * pt(a.cos(), a.sin());
+        let via = place.create_via(pin.net_id, q);
+        for layer in pin.layers.iter() {
+            let wire = place.create_wire(pin.net_id, layer, &[pin.p, q])?;
+            if place.is_wire_blocked(&wire) || place.is_via_blocked_by_other_nets(&via) {
+                continue;
+            }
+            place.add_wire(&wire);
+            place.add_via(&via);
+            res.wires.push(wire);
+            res.vias.push(via);
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use memegeom::primitive::shape::Shape;
+
+    use super::*;
+    use crate::dsn::load_dsn;
+
+    // A 2x2 grid of balls with a 2 mm pitch centered on (10, 10), each on its own net.
+    const BGA_DSN: &str = r#"
+(pcb bga
+  (parser
+    (string_quote ")
+    (space_in_quoted_tokens on)
+  )
+  (resolution um 10)
+  (unit um)
+  (structure
+    (layer F.Cu (type signal))
+    (layer B.Cu (type signal))
+    (boundary (rect pcb 0 0 20000 20000))
+    (via via600)
+    (rule (width 200) (clearance 200))
+  )
+  (placement
+    (component bga
+      (place U1 10000 10000 front 0)
+    )
+  )
+  (library
+    (image bga
+      (pin ball 1 -1000 -1000)
+      (pin ball 2 1000 -1000)
+      (pin ball 3 -1000 1000)
+      (pin ball 4 1000 1000)
+    )
+    (padstack ball
+      (shape (circle F.Cu 400))
+      (attach off)
+    )
+    (padstack via600
+      (shape (circle F.Cu 600))
+      (shape (circle B.Cu 600))
+      (attach off)
+    )
+  )
+  (network
+    (net N1 (pins U1-1))
+    (net N2 (pins U1-2))
+    (net N3 (pins U1-3))
+    (net N4 (pins U1-4))
+    (class default N1 N2 N3 N4 (rule (width 200) (clearance 200)))
+  )
+  (wiring)
+)
+"#;
+
+    #[test]
+    fn escape_bga_outwards() {
+        let pcb = load_dsn(BGA_DSN).unwrap();
+        let res = escape_pins(&pcb, pcb.to_id("U1")).unwrap();
+        assert!(!res.failed, "{:?}", res.failed_nets);
+        assert!(res.vias.is_empty());
+        assert_eq!(res.wires.len(), 4);
+        let mid = pt(10.0, 10.0);
+        for wire in &res.wires {
+            let Shape::Path(p) = &wire.shape.shape else { panic!("wire is not a path") };
+            let (start, end) = (p.pts()[0], p.pts()[p.pts().len() - 1]);
+            // Each stub starts at a ball and ends outside the grid, without
+            // going far.
+            assert!(eq(start.dist(mid), 2.0_f64.sqrt()), "{start:?}");
+            assert!(end.dist(mid) > start.dist(mid) + 1.0, "{end:?}");
+            assert!(start.dist(end) < 3.0, "{start:?} {end:?}");
+        }
+        let mut nets: Vec<Id> = res.wires.iter().map(|w| w.net_id).collect();
+        nets.sort_unstable();
+        nets.dedup();
+        assert_eq!(nets.len(), 4);
+    }
+}
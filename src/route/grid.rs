@@ -1,5 +1,7 @@
-use ahash::HashMap;
+use ahash::{HashMap, HashSet};
 use eyre::{eyre, Result};
+use log::debug;
+use memegeom::geom::bounds::rt_cloud_bounds;
 use memegeom::geom::math::f64_cmp;
 use memegeom::geom::qt::query::TagQuery;
 use memegeom::primitive::point::{Pt, PtI};
@@ -8,13 +10,20 @@ use memegeom::primitive::{circ, pt, pti, ShapeOps};
 use memegeom::tf::Tf;
 use ordered_float::OrderedFloat;
 use priority_queue::PriorityQueue;
+use rayon::prelude::*;
 
-use crate::model::pcb::{LayerSet, LayerShape, ObjectKind, Pcb, PinRef, Via, Wire};
+use crate::model::pcb::{LayerSet, LayerShape, Net, ObjectKind, Pcb, Pin, PinRef, Via, Wire};
 use crate::name::{Id, NO_ID};
+use crate::route::arcs::round_corners;
 use crate::route::place_model::PlaceModel;
 use crate::route::router::{RouteResult, RouteStrategy};
 
 const VIA_COST: f64 = 10.0;
+const NECK_DOWN_COST: f64 = 2.0; // Extra cost per grid step for necked down wires.
+const BUS_CHANNEL_FACTOR: f64 = 0.5; // Cost multiplier for steps next to other nets in a bus.
+const BATCH_MARGIN: f64 = 2.0; // Extra space around a net's pins when batching, in mm.
+const DIFF_PAIR_DIVERGE_COST: f64 = 2.0; // Extra cost per step away from a pair's partner.
+const SHARP_TURN_COST: f64 = 1.0; // Extra cost for turns of 90 degrees or more when octilinear.
 
 const DIR: [(PtI, f64); 9] = [
     (pti(-1, 0), 1.0),
@@ -28,12 +37,23 @@ const DIR: [(PtI, f64); 9] = [
     (pti(0, 0), VIA_COST),
 ];
 
+// Which directions wires may go in.
+#[must_use]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum RoutingStyle {
+    #[default]
+    Any, // All eight directions, with no preference for how to turn.
+    Orthogonal, // Only horizontal and vertical.
+    Octilinear, // All eight directions, but prefer 45 degree turns.
+}
+
 #[must_use]
 #[derive(Debug, Default, Hash, Copy, Clone, PartialEq, Eq)]
 pub struct State {
     pub p: PtI,
     pub layers: LayerSet,
     pub net_id: Id,
+    pub dir: PtI, // Direction of the last step. Only tracked for octilinear routing.
 }
 
 #[must_use]
@@ -58,29 +78,226 @@ pub struct GridRouter {
     resolution: f64,
     place: PlaceModel,
     net_order: Vec<Id>,
+    keep_zero_length_wires: bool,
+    neck_down_radius: Option<f64>,
+    style: RoutingStyle,
+    arc_radius: Option<f64>,
+    parallel: bool,
+    diff_pair_gap: f64,
+    bus_groups: Vec<Vec<Id>>,
+    routed_states: HashMap<Id, Vec<State>>, // Grid states used by each routed net.
 }
 
 impl GridRouter {
     pub fn new(pcb: Pcb, net_order: Vec<Id>) -> Self {
+        // Use the board's declared routing grid if it has one.
+        let resolution = pcb.wire_grid().unwrap_or(0.4);
         let place = PlaceModel::new(pcb);
-        Self { resolution: 0.4, place, net_order }
+        Self {
+            resolution,
+            place,
+            net_order,
+            keep_zero_length_wires: false,
+            neck_down_radius: None,
+            style: RoutingStyle::default(),
+            arc_radius: None,
+            parallel: false,
+            diff_pair_gap: 0.0,
+            bus_groups: Vec::new(),
+            routed_states: HashMap::default(),
+        }
+    }
+
+    pub fn with_resolution(pcb: Pcb, net_order: Vec<Id>, resolution: f64) -> Result<Self> {
+        let mut r = Self::new(pcb, net_order);
+        r.set_resolution(resolution)?;
+        Ok(r)
+    }
+
+    // Sets the size of a grid square, in mm. Smaller is slower but can route
+    // finer pitch boards.
+    pub fn set_resolution(&mut self, resolution: f64) -> Result<()> {
+        if resolution.is_nan() || resolution <= 0.0 {
+            return Err(eyre!("grid resolution must be positive, got {}", resolution));
+        }
+        self.resolution = resolution;
+        Ok(())
+    }
+
+    // Number of collision queries made while routing so far.
+    #[must_use]
+    pub fn query_count(&self) -> usize {
+        self.place.query_count()
+    }
+
+    // If set, wires may narrow to this radius where their normal width is blocked.
+    pub fn set_neck_down_radius(&mut self, r: Option<f64>) {
+        self.neck_down_radius = r;
+    }
+
+    // Whether to output wires which start and end in the same grid square,
+    // e.g. wires between two vias in the same location.
+    pub fn set_keep_zero_length_wires(&mut self, keep: bool) {
+        self.keep_zero_length_wires = keep;
+    }
+
+    pub fn set_routing_style(&mut self, style: RoutingStyle) {
+        self.style = style;
+    }
+
+    // If set, corners in routed wires are replaced with arcs of this radius
+    // where there is room.
+    pub fn set_arc_radius(&mut self, r: Option<f64>) {
+        self.arc_radius = r;
+    }
+
+    // Whether to route nets far apart from each other in parallel. This is
+    // faster on large boards, but the output may differ from routing nets one
+    // at a time in order.
+    pub fn set_parallel(&mut self, parallel: bool) {
+        self.parallel = parallel;
+    }
+
+    // Sets how far apart, in mm, the wires of a differential pair may be.
+    // The second net of a pair to be routed is kept within this distance of
+    // the first where possible, and both nets fail if it can't be. Pairs are
+    // routed like any other nets until this is set.
+    pub fn set_diff_pair_gap(&mut self, gap: f64) {
+        self.diff_pair_gap = gap;
+    }
+
+    // Adds a group of nets which form a bus. Routing is cheaper next to wires
+    // of other nets in the same bus, so buses tend to run in parallel channels.
+    pub fn add_bus_group(&mut self, nets: Vec<Id>) {
+        self.bus_groups.push(nets);
+    }
+
+    // Adds all nets with names starting with |prefix| as a bus group.
+    pub fn add_bus_group_by_prefix(&mut self, prefix: &str) {
+        let pcb = self.place.pcb();
+        let nets: Vec<_> =
+            pcb.nets().filter(|v| pcb.to_name(v.id).starts_with(prefix)).map(|v| v.id).collect();
+        self.add_bus_group(nets);
+    }
+
+    // Returns grid points and layers next to routed wires of other nets in the
+    // same bus as the given net.
+    fn bus_channel(&self, net_id: Id) -> HashSet<(PtI, LayerSet)> {
+        let mut channel = HashSet::default();
+        let groups = self.bus_groups.iter().filter(|g| g.contains(&net_id));
+        for &other in groups.flatten().filter(|&&v| v != net_id) {
+            for s in self.routed_states.get(&other).into_iter().flatten() {
+                for (dp, _) in DIR.iter().filter(|(dp, _)| !dp.is_zero()) {
+                    channel.insert((s.p + *dp, s.layers));
+                }
+            }
+        }
+        channel
+    }
+
+    // Returns grid points and layers within the differential pair gap of the
+    // routed wires of the given net's partner, or None if the net isn't in a
+    // pair, its partner hasn't been routed yet, or no gap is set.
+    fn diff_pair_channel(&self, net_id: Id) -> Option<HashSet<(PtI, LayerSet)>> {
+        if self.diff_pair_gap <= 0.0 {
+            return None;
+        }
+        let partner = self.place.pcb().diff_pair_partner(net_id)?;
+        let states = self.routed_states.get(&partner)?;
+        // Allow one extra cell since wires are snapped to the grid.
+        let cells = (self.diff_pair_gap / self.resolution).ceil() as i64 + 1;
+        let mut channel = HashSet::default();
+        for s in states {
+            for dx in -cells..=cells {
+                for dy in -cells..=cells {
+                    channel.insert((s.p + pti(dx, dy), s.layers));
+                }
+            }
+        }
+        Some(channel)
+    }
+
+    // Whether the routed wires of the given net stay within the differential
+    // pair gap of its partner. Nets without a routed partner always do.
+    fn is_within_pair_gap(&self, net_id: Id) -> bool {
+        let Some(channel) = self.diff_pair_channel(net_id) else { return true };
+        self.routed_states
+            .get(&net_id)
+            .into_iter()
+            .flatten()
+            .all(|s| channel.contains(&(s.p, s.layers)))
     }
 
     fn pin_ref_state(&self, pin_ref: &PinRef) -> Result<State> {
         let (component, pin) = self.place.pcb().pin_ref(pin_ref)?;
         let p = self.grid_pt((component.tf() * pin.tf()).pt(Pt::zero()));
         // TODO: Assumes connect to the center of the pin. Look at padstack instead.
-        let layers = pin.padstack.shapes.iter().map(|v| v.layers).collect();
+        // Shapes on e.g. mask layers are kept for rendering but can't be routed to.
+        let layers = pin.padstack.shapes.iter().map(|v| v.layers).collect::<LayerSet>()
+            & self.place.pcb().copper_layers();
         let net_id =
             self.place.pcb().pin_ref_net(pin_ref).ok_or_else(|| eyre!("missing net id"))?;
-        Ok(State { p, layers, net_id })
+        let p = self.free_pin_pt(p, pin, layers, net_id)?;
+        Ok(State { p, layers, net_id, dir: pti(0, 0) })
     }
 
-    fn wire_from_states(&self, states: &[State]) -> Wire {
+    // If the center of a pin is blocked, e.g. by an overlapping keepout, try to
+    // connect to another free point on the pin instead.
+    fn free_pin_pt(&self, p: PtI, pin: &Pin, layers: LayerSet, net_id: Id) -> Result<PtI> {
+        let Some(layer) = layers.first() else { return Ok(p) };
+        let mid = self.world_pt_mid(p);
+        if !self.place.is_wire_blocked(&self.place.create_wire(net_id, layer, &[mid])?) {
+            return Ok(p);
+        }
+        // Stay within the pin so the connection is still made through the pad.
+        let r = pin
+            .padstack
+            .shapes
+            .iter()
+            .map(|s| {
+                let b = s.shape.bounds();
+                (b.r() - b.l()).min(b.t() - b.b()) / 2.0
+            })
+            .fold(0.0, f64::max);
+        Ok(self
+            .place
+            .nearest_free(mid, layer, net_id, r)?
+            .map_or(p, |free| self.grid_pt(free)))
+    }
+
+    fn wire_from_states(&self, states: &[State]) -> Result<Wire> {
         let pts: Vec<_> = states.iter().map(|s| self.world_pt_mid(s.p)).collect();
         self.place.create_wire(states[0].net_id, states[0].layers.id().unwrap(), &pts)
     }
 
+    // Creates wires through the given states, necking down segments where needed.
+    fn wires_from_states(&self, states: &[State]) -> Result<Vec<Wire>> {
+        if states.len() < 2 || self.neck_down_radius.is_none() {
+            return Ok(vec![self.wire_from_states(states)?]);
+        }
+        let (net_id, layer) = (states[0].net_id, states[0].layers.id().unwrap());
+        let default_r = self.place.pcb().net_radius(net_id, layer)?;
+        let pts: Vec<_> = states.iter().map(|s| self.world_pt_mid(s.p)).collect();
+        let radii: Vec<_> = states
+            .windows(2)
+            .map(|v| Ok(self.segment_radius(&v[0], &v[1])?.map_or(default_r, |(r, _)| r)))
+            .collect::<Result<_>>()?;
+        Wire::with_radii(net_id, states[0].layers, &pts, &radii)
+    }
+
+    // Returns the radius to use for a wire between two adjacent states, and
+    // whether it was necked down, or None if it is blocked.
+    fn segment_radius(&self, a: &State, b: &State) -> Result<Option<(f64, bool)>> {
+        let layer = a.layers.id().unwrap();
+        if !self.place.is_wire_blocked(&self.wire_from_states(&[*a, *b])?) {
+            return Ok(Some((self.place.pcb().net_radius(a.net_id, layer)?, false)));
+        }
+        let Some(r) = self.neck_down_radius else { return Ok(None) };
+        let pts = [self.world_pt_mid(a.p), self.world_pt_mid(b.p)];
+        let wire = self.place.create_wire_radius(a.net_id, layer, &pts, r);
+        Ok((!self.place.is_wire_blocked(&wire)).then_some((r, true)))
+    }
+
     fn via_from_state(&self, state: &State) -> Via {
         self.place.create_via(state.net_id, self.world_pt_mid(state.p))
     }
@@ -98,54 +315,67 @@ impl GridRouter {
         self.world_pt(p) + pt(self.resolution / 2.0, self.resolution / 2.0)
     }
 
-    fn push_path(
-        &self,
-        wires: &mut Vec<Wire>,
-        vias: &mut Vec<Via>,
-        cur: &mut Vec<State>,
-        last: bool,
-    ) {
-        let l = cur.len();
-        if cur.is_empty() {
-            return;
-        }
-        let is_via = l >= 2 && cur[l - 1].layers != cur[l - 2].layers;
-        // Add the via.
-        if is_via {
-            vias.push(self.via_from_state(&cur[l - 1]));
-        }
-        // Add the wire, if it exists.
-        if is_via || last {
-            // TODO: Assumes wire width some proportion of resolution.
-            // Keeps duplicated last point if we made a via. That allows for
-            // wires that only take up one square.
-            wires.push(self.wire_from_states(cur));
-            // Only keep the last element - for starting next wire in the same
-            // location as the via.
-            cur.swap(0, l - 1);
-            cur.truncate(1);
-        }
-    }
-
-    fn create_path(&self, path: &[State]) -> (Vec<Wire>, Vec<Via>) {
+    // Adds wires for a run of states on a single layer. Zero length runs are
+    // dropped unless |keep_zero_length_wires| is set.
+    fn push_run(&self, wires: &mut Vec<Wire>, run: &[State]) -> Result<()> {
+        if run.is_empty() {
+            return Ok(());
+        }
+        // TODO: Assumes wire width some proportion of resolution.
+        let zero_length = run.iter().all(|v| v.p == run[0].p);
+        if self.keep_zero_length_wires || !zero_length {
+            wires.extend(self.wires_from_states(run)?);
+        }
+        Ok(())
+    }
+
+    // Converts a path into wires and vias. The path is split into runs of
+    // states on the same layer. A layer change always happens in place, so
+    // the last state of a run and the first state of the next run have the
+    // same point, which is where the via goes. Each run becomes wires on its
+    // own layer, so wires always end at, and start from, the vias joining them.
+    fn create_path(&self, path: &[State]) -> Result<(Vec<Wire>, Vec<Via>)> {
         let mut wires = Vec::new();
         let mut vias = Vec::new();
-        let mut cur_states = Vec::new();
-        for cur in path {
-            self.push_path(&mut wires, &mut vias, &mut cur_states, false);
-            cur_states.push(*cur);
+        let mut run: Vec<State> = Vec::new();
+        for &s in path {
+            if run.last().is_some_and(|v| v.layers != s.layers) {
+                vias.push(self.via_from_state(&s));
+                self.push_run(&mut wires, &run)?;
+                run.clear();
+            }
+            run.push(s);
         }
-        self.push_path(&mut wires, &mut vias, &mut cur_states, true);
-        (wires, vias)
+        self.push_run(&mut wires, &run)?;
+        Ok((wires, vias))
+    }
+
+    // Layers the given net may be routed on.
+    fn net_layers(&self, net_id: Id) -> LayerSet {
+        let pcb = self.place.pcb();
+        pcb.net_ruleset(net_id).allowed_layers().unwrap_or_else(|| pcb.copper_layers())
     }
 
-    fn dijkstra(&self, srcs: &[State], dsts: &[State]) -> Vec<State> {
+    // A* heuristic. Minimum distance from |p| to a destination, in grid steps
+    // so it's in the same units as the edge costs.
+    fn heuristic(&self, dsts: &[State], p: PtI) -> f64 {
+        let dist_fn = |d: &State| self.world_pt_mid(d.p).dist(self.world_pt_mid(p));
+        dsts.iter().map(dist_fn).min_by(f64_cmp).unwrap() / self.resolution
+    }
+
+    fn dijkstra(&self, srcs: &[State], dsts: &[State]) -> Result<Vec<State>> {
         let mut q: PriorityQueue<State, OrderedFloat<f64>> = PriorityQueue::new();
         let mut node_data: HashMap<State, NodeData> = HashMap::default();
+        let allowed = self.net_layers(srcs[0].net_id);
+        let channel = self.bus_channel(srcs[0].net_id);
+        // Steps in a bus channel are cheaper, so scale the heuristic down to
+        // match, otherwise it overestimates and the search ignores the channel.
+        let h_scale = if channel.is_empty() { 1.0 } else { BUS_CHANNEL_FACTOR };
+        let pair_channel = self.diff_pair_channel(srcs[0].net_id);
 
         for src in srcs {
             // Try going from each of the valid layers in this state.
-            for layer in src.layers.iter() {
+            for layer in (src.layers & allowed).iter() {
                 let s = State { layers: LayerSet::one(layer), ..*src };
                 q.push(s, OrderedFloat(0.0));
                 node_data.insert(s, NodeData { prev: State::default(), cost: 0.0, seen: true });
@@ -158,6 +388,10 @@ impl GridRouter {
 
             for (dp, edge_cost) in DIR {
                 let is_via = dp.is_zero();
+                let is_diagonal = dp.x != 0 && dp.y != 0;
+                if self.style == RoutingStyle::Orthogonal && is_diagonal {
+                    continue;
+                }
                 let cur_layer = cur.layers.id().unwrap(); // Should only be one layer.
                 let layers = if is_via {
                     let mut layers = self.via_from_state(&cur).padstack.layers();
@@ -167,23 +401,49 @@ impl GridRouter {
                 } else {
                     LayerSet::one(cur_layer)
                 };
-                for layer in layers.iter() {
+                for layer in (layers & allowed).iter() {
+                    // Only keep the direction when it affects the cost, so other
+                    // styles don't search each point once per direction.
+                    let dir = if self.style == RoutingStyle::Octilinear { dp } else { pti(0, 0) };
                     let next = State {
                         p: cur.p + dp,
                         layers: LayerSet::one(layer),
                         net_id: srcs[0].net_id,
+                        dir,
                     };
-                    let cost = cur_cost + edge_cost;
+                    let mut cost = cur_cost + edge_cost;
+                    if !is_via && channel.contains(&(next.p, next.layers)) {
+                        cost -= edge_cost * (1.0 - BUS_CHANNEL_FACTOR);
+                    }
+                    if let Some(pair_channel) = &pair_channel {
+                        if !is_via && !pair_channel.contains(&(next.p, next.layers)) {
+                            cost += edge_cost * DIFF_PAIR_DIVERGE_COST;
+                        }
+                    }
+                    // Turning by 90 degrees or more has a non-positive dot product.
+                    // Sources and vias have no direction, so any turn is free.
+                    let dot = cur.dir.x * dp.x + cur.dir.y * dp.y;
+                    if self.style == RoutingStyle::Octilinear
+                        && !is_via
+                        && !cur.dir.is_zero()
+                        && dot <= 0
+                    {
+                        cost += SHARP_TURN_COST;
+                    }
                     let data = node_data.entry(next).or_insert_with(Default::default);
 
                     if data.seen {
                         continue;
                     }
 
-                    let wire = self.wire_from_states(&[cur, next]);
                     // Wire is blocked if anything other than its net is there.
-                    if !is_via && self.place.is_wire_blocked(&wire) {
-                        continue;
+                    if !is_via {
+                        let Some((_, necked)) = self.segment_radius(&cur, &next)? else {
+                            continue;
+                        };
+                        if necked {
+                            cost += NECK_DOWN_COST;
+                        }
                     }
 
                     // Vias are blocked by anything since they create a hole.
@@ -196,10 +456,7 @@ impl GridRouter {
                         data.cost = cost;
                         data.prev = cur;
 
-                        // A* heuristic. Minimum distance to a destination.
-                        let dist_fn =
-                            |d: &State| self.world_pt_mid(d.p).dist(self.world_pt_mid(next.p));
-                        let heuristic = dsts.iter().map(dist_fn).min_by(f64_cmp).unwrap();
+                        let heuristic = h_scale * self.heuristic(dsts, next.p);
                         q.push(next, OrderedFloat(-(cost + heuristic)));
                     }
                 }
@@ -230,26 +487,139 @@ impl GridRouter {
             // Should reach the end of the path.
             assert_eq!(cur, State::default());
             path.reverse();
-            path
+            Ok(path)
         } else {
-            vec![]
+            Ok(vec![])
+        }
+    }
+
+    // Routes between two points on the given layers, without adding anything to
+    // the place model. Mainly useful for debugging the search.
+    pub fn route_points(
+        &mut self,
+        a: Pt,
+        b: Pt,
+        layers: LayerSet,
+        net_id: Id,
+    ) -> Result<Vec<Wire>> {
+        let src = State { p: self.grid_pt(a), layers, net_id, dir: pti(0, 0) };
+        let dst = State { p: self.grid_pt(b), layers, net_id, dir: pti(0, 0) };
+        let path = self.dijkstra(&[src], &[dst])?;
+        if path.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(self.create_path(&path)?.0)
+    }
+
+    // Estimates congestion for each grid cell on the board as the fraction of the
+    // cell and its neighbours which are blocked, over all layers. Values are in
+    // [0, 1]. Useful for showing as a heatmap to guide placement.
+    pub fn congestion_map(&self) -> Result<HashMap<PtI, f64>> {
+        let pcb = self.place.pcb();
+        let bounds = pcb.content_bounds();
+        let (lo, hi) = (self.grid_pt(bounds.bl()), self.grid_pt(bounds.tr()));
+        let layers = pcb.copper_layers();
+        let num_layers = layers.iter().count().max(1);
+
+        // Number of layers blocked in each cell.
+        let mut blocked: HashMap<PtI, usize> = HashMap::default();
+        for x in lo.x..=hi.x {
+            for y in lo.y..=hi.y {
+                let p = pti(x, y);
+                let mid = self.world_pt_mid(p);
+                let mut count = 0;
+                for l in layers.iter() {
+                    if self.place.is_wire_blocked(&self.place.create_wire(NO_ID, l, &[mid])?) {
+                        count += 1;
+                    }
+                }
+                blocked.insert(p, count);
+            }
+        }
+
+        Ok(blocked
+            .keys()
+            .map(|&p| {
+                // DIR includes the zero offset, so this covers the cell itself too.
+                let counts: Vec<usize> =
+                    DIR.iter().filter_map(|&(dp, _)| blocked.get(&(p + dp)).copied()).collect();
+                let total: usize = counts.iter().sum();
+                (p, total as f64 / (counts.len() * num_layers) as f64)
+            })
+            .collect())
+    }
+
+    // Groups the pin states of a net into sets which are already connected to
+    // each other by existing wires of that net.
+    fn connected_groups(&self, net: &Net, states: Vec<State>) -> Result<Vec<Vec<State>>> {
+        fn find(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        // Always links towards the smaller index, so any group containing a pin
+        // has a pin as its root.
+        fn union(parent: &mut [usize], a: usize, b: usize) {
+            let (x, y) = (find(parent, a), find(parent, b));
+            parent[x.max(y)] = x.min(y);
         }
+        fn touches(a: &LayerShape, b: &LayerShape) -> bool {
+            !(a.layers & b.layers).is_empty() && a.shape.intersects_shape(&b.shape)
+        }
+
+        let pcb = self.place.pcb();
+        let wires: Vec<&Wire> = pcb.net_wires(net.id).collect();
+        let mut pin_shapes = Vec::new();
+        for (idx, p) in net.pins.iter().enumerate() {
+            let (component, pin) = pcb.pin_ref(p)?;
+            let tf = component.tf() * pin.tf();
+            for s in &pin.padstack.shapes {
+                pin_shapes.push((idx, LayerShape { layers: s.layers, shape: tf.shape(&s.shape) }));
+            }
+        }
+
+        // Union find over pins, followed by wires.
+        let n = net.pins.len();
+        let mut parent: Vec<usize> = (0..n + wires.len()).collect();
+        for (i, a) in wires.iter().enumerate() {
+            for (idx, s) in &pin_shapes {
+                if touches(&a.shape, s) {
+                    union(&mut parent, n + i, *idx);
+                }
+            }
+            for (j, b) in wires.iter().enumerate().skip(i + 1) {
+                if touches(&a.shape, &b.shape) {
+                    union(&mut parent, n + i, n + j);
+                }
+            }
+        }
+
+        let mut groups = vec![Vec::new(); n];
+        for (idx, state) in states.into_iter().enumerate() {
+            groups[find(&mut parent, idx)].push(state);
+        }
+        groups.retain(|g| !g.is_empty());
+        Ok(groups)
     }
 
-    // Connect the given states together and return a route result doing that.
-    fn connect(&mut self, mut srcs: Vec<State>) -> RouteResult {
+    // Connect the given groups of states together and return a route result doing that.
+    // States within a group are assumed to already be connected.
+    fn connect(&mut self, mut groups: Vec<Vec<State>>) -> Result<RouteResult> {
         let mut res = RouteResult::default();
-        if srcs.len() <= 1 {
-            return res;
+        if groups.len() <= 1 {
+            return Ok(res);
         }
-        let mut dsts = srcs.split_off(1);
-        while !dsts.is_empty() {
-            let path = self.dijkstra(&srcs, &dsts);
+        let mut srcs = groups.swap_remove(0);
+        while !groups.is_empty() {
+            let dsts: Vec<State> = groups.iter().flatten().copied().collect();
+            let path = self.dijkstra(&srcs, &dsts)?;
             if path.is_empty() {
                 res.failed = true;
-                return res;
+                return Ok(res);
             }
-            let (wires, vias) = self.create_path(&path);
+            let (wires, vias) = self.create_path(&path)?;
             for wire in &wires {
                 self.place.add_wire(wire);
             }
@@ -258,16 +628,158 @@ impl GridRouter {
             }
             res.wires.extend(wires);
             res.vias.extend(vias);
+            self.routed_states.entry(path[0].net_id).or_default().extend(&path);
             // Assume the last state in the path is a destination.
             let dst = path.last().unwrap();
-            let idx = dsts
+            let idx = groups
                 .iter()
-                .position(|v| v.p == dst.p && v.layers.contains_set(dst.layers))
+                .position(|g| g.iter().any(|v| v.p == dst.p && v.layers.contains_set(dst.layers)))
                 .unwrap();
-            srcs.push(dsts.swap_remove(idx));
+            srcs.extend(groups.swap_remove(idx));
         }
 
-        res
+        Ok(res)
+    }
+
+    // Routes a single net. If |keep_existing| is set, existing wires for the net
+    // are kept and only the gaps between them are routed.
+    fn route_net(&mut self, net_id: Id, keep_existing: bool) -> Result<RouteResult> {
+        let net =
+            self.place.pcb().net(net_id).ok_or_else(|| eyre!("missing net {}", net_id))?.clone();
+        // Fixed nets keep their existing wires, which are already in the place model.
+        if net.is_fixed() {
+            return Ok(RouteResult::default());
+        }
+        let states: Vec<State> =
+            net.pins.iter().map(|p| self.pin_ref_state(p)).collect::<Result<_>>()?;
+
+        let groups = if keep_existing {
+            self.connected_groups(&net, states)?
+        } else {
+            states.into_iter().map(|v| vec![v]).collect()
+        };
+        let mut res = self.connect(groups)?;
+        // The pair gap is only a cost during the search, so check it was kept.
+        if !res.failed && !self.is_within_pair_gap(net_id) {
+            res.failed = true;
+            res.failed_nets.extend(self.place.pcb().diff_pair_partner(net_id));
+        }
+        if res.failed && !res.failed_nets.contains(&net_id) {
+            res.failed_nets.push(net_id);
+        }
+        // Mark wires and vias.
+        for wire in &res.wires {
+            self.place.add_wire(wire);
+        }
+        for via in &res.vias {
+            self.place.add_via(via);
+        }
+        Ok(res)
+    }
+
+    // Changes the wire radius of the given net and reroutes it.
+    pub fn set_net_radius(&mut self, net_id: Id, radius: f64) -> Result<RouteResult> {
+        self.place.set_net_radius(net_id, radius);
+        self.reroute_net(net_id)
+    }
+
+    // Removes the wires and vias of the given net and routes it again from
+    // scratch. Other nets' geometry is left untouched.
+    pub fn reroute_net(&mut self, net_id: Id) -> Result<RouteResult> {
+        let net = self.place.pcb().net(net_id).ok_or_else(|| eyre!("missing net {}", net_id))?;
+        if net.is_fixed() {
+            return Err(eyre!("can't reroute fixed net {}", self.place.pcb().to_name(net_id)));
+        }
+        self.place.remove_net_routing(net_id);
+        self.routed_states.remove(&net_id);
+        self.route_net(net_id, false)
+    }
+
+    // Bounding box of the pins of a net, with some margin for the wires.
+    fn net_bounds(&self, net_id: Id) -> Result<Rt> {
+        let net = self.place.pcb().net(net_id).ok_or_else(|| eyre!("missing net {}", net_id))?;
+        let rts = net
+            .pins
+            .iter()
+            .map(|p| {
+                let p = self.world_pt_mid(self.pin_ref_state(p)?.p);
+                Ok(Rt::enclosing(p, p))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(rt_cloud_bounds(rts.into_iter()).inset(-BATCH_MARGIN, -BATCH_MARGIN))
+    }
+
+    // Splits nets into batches, keeping net order, such that nets in the same
+    // batch have non-overlapping bounds.
+    fn net_batches(&self) -> Result<Vec<Vec<Id>>> {
+        let mut batches: Vec<Vec<(Id, Rt)>> = Vec::new();
+        for &net_id in &self.net_order {
+            let b = self.net_bounds(net_id)?;
+            // Only add to the last batch, so nets are routed no earlier than
+            // nets before them in the order.
+            match batches.last_mut() {
+                Some(batch) if batch.iter().all(|(_, v)| !v.intersects_shape(&b.shape())) => {
+                    batch.push((net_id, b));
+                }
+                _ => batches.push(vec![(net_id, b)]),
+            }
+        }
+        Ok(batches.into_iter().map(|v| v.into_iter().map(|(id, _)| id).collect()).collect())
+    }
+
+    fn is_result_blocked(&self, res: &RouteResult) -> bool {
+        res.wires.iter().any(|v| self.place.is_wire_blocked(v))
+            || res.vias.iter().any(|v| self.place.is_via_blocked_by_other_nets(v))
+    }
+
+    fn log_net(&self, net_id: Id, res: &RouteResult) {
+        debug!("done {}, failed {}", self.place.pcb().to_name(net_id), res.failed);
+    }
+
+    // Routes each batch of nets in parallel, splitting the batch into a chunk
+    // per thread with each chunk routed on its own copy of the router. Nets
+    // whose routing conflicts with nets merged before them are rerouted
+    // sequentially.
+    fn route_parallel(&mut self) -> Result<RouteResult> {
+        let mut res = RouteResult::default();
+        for batch in self.net_batches()? {
+            // Clone up front, since the place model can't be shared between threads.
+            let chunk_size = batch.len().div_ceil(rayon::current_num_threads());
+            let routers: Vec<_> =
+                batch.chunks(chunk_size).map(|nets| (nets.to_vec(), self.clone())).collect();
+            let routed = routers
+                .into_par_iter()
+                .map(|(nets, mut r)| {
+                    nets.into_iter()
+                        .map(|net_id| {
+                            let res = r.route_net(net_id, true)?;
+                            Ok((net_id, res, r.routed_states.remove(&net_id)))
+                        })
+                        .collect::<Result<Vec<_>>>()
+                })
+                .collect::<Result<Vec<_>>>()?;
+            for (net_id, net_res, states) in routed.into_iter().flatten() {
+                if self.is_result_blocked(&net_res) {
+                    self.routed_states.remove(&net_id);
+                    let net_res = self.route_net(net_id, true)?;
+                    self.log_net(net_id, &net_res);
+                    res.merge(net_res);
+                    continue;
+                }
+                for wire in &net_res.wires {
+                    self.place.add_wire(wire);
+                }
+                for via in &net_res.vias {
+                    self.place.add_via(via);
+                }
+                if let Some(states) = states {
+                    self.routed_states.insert(net_id, states);
+                }
+                self.log_net(net_id, &net_res);
+                res.merge(net_res);
+            }
+        }
+        Ok(res)
     }
 
     fn _draw_debug(&mut self, res: &mut RouteResult) {
@@ -304,29 +816,454 @@ impl GridRouter {
 
 impl RouteStrategy for GridRouter {
     fn route(&mut self) -> Result<RouteResult> {
-        let mut res = RouteResult::default();
-        for net_id in self.net_order.clone() {
-            let net = self
-                .place
-                .pcb()
-                .net(net_id)
-                .ok_or_else(|| eyre!("missing net {}", net_id))?
-                .clone();
-            let states = net.pins.iter().map(|p| self.pin_ref_state(p)).collect::<Result<_>>()?;
-
-            let sub_result = self.connect(states);
-            println!("done {}, failed {}", self.place.pcb().to_name(net_id), sub_result.failed);
-            // Mark wires and vias.
-            for wire in &sub_result.wires {
-                self.place.add_wire(wire);
-            }
-            for via in &sub_result.vias {
-                self.place.add_via(via);
+        let mut res = if self.parallel {
+            self.route_parallel()?
+        } else {
+            let mut res = RouteResult::default();
+            for net_id in self.net_order.clone() {
+                let net_res = self.route_net(net_id, true)?;
+                self.log_net(net_id, &net_res);
+                res.merge(net_res);
             }
-            res.merge(sub_result);
+            res
+        };
+        if let Some(r) = self.arc_radius {
+            round_corners(&self.place, &mut res, r);
         }
 
         // self.draw_debug(&mut res);
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use memegeom::geom::math::{eq, pt_eq};
+    use memegeom::primitive::shape::Shape;
+    use memegeom::primitive::{path, rt};
+
+    use super::*;
+    use crate::dsn::load_dsn;
+    use crate::model::pcb::{Keepout, KeepoutType, LayerId};
+    use crate::testing::{test_pcb, TEST_DSN};
+
+    fn router() -> (GridRouter, Id) {
+        let pcb = test_pcb();
+        let net_id = pcb.to_id("A");
+        (GridRouter::new(pcb, vec![net_id]), net_id)
+    }
+
+    fn state(x: i64, y: i64, layer: LayerId, net_id: Id) -> State {
+        State { p: pti(x, y), layers: LayerSet::one(layer), net_id, dir: pti(0, 0) }
+    }
+
+    fn assert_wire(r: &GridRouter, wire: &Wire, layer: LayerId, pts: &[(i64, i64)]) {
+        assert_eq!(wire.shape.layers, LayerSet::one(layer));
+        let Shape::Path(s) = &wire.shape.shape else { panic!("wire is not a path") };
+        assert_eq!(s.pts().len(), pts.len());
+        for (&a, &(x, y)) in s.pts().iter().zip(pts) {
+            assert!(pt_eq(a, r.world_pt_mid(pti(x, y))), "{a:?} != ({x}, {y})");
+        }
+    }
+
+    fn assert_vias(r: &GridRouter, vias: &[Via], pts: &[(i64, i64)]) {
+        assert_eq!(vias.len(), pts.len());
+        for (via, &(x, y)) in vias.iter().zip(pts) {
+            assert!(pt_eq(via.p, r.world_pt_mid(pti(x, y))), "{:?} != ({x}, {y})", via.p);
+        }
+    }
+
+    #[test]
+    fn create_path_straight_run() {
+        let (r, n) = router();
+        let path = [state(0, 0, 0, n), state(1, 0, 0, n), state(2, 0, 0, n)];
+        let (wires, vias) = r.create_path(&path).unwrap();
+        assert_eq!(wires.len(), 1);
+        assert_wire(&r, &wires[0], 0, &[(0, 0), (1, 0), (2, 0)]);
+        assert_vias(&r, &vias, &[]);
+    }
+
+    #[test]
+    fn create_path_single_via() {
+        let (r, n) = router();
+        let path = [state(0, 0, 0, n), state(1, 0, 0, n), state(1, 0, 1, n), state(2, 0, 1, n)];
+        let (wires, vias) = r.create_path(&path).unwrap();
+        assert_eq!(wires.len(), 2);
+        assert_wire(&r, &wires[0], 0, &[(0, 0), (1, 0)]);
+        assert_wire(&r, &wires[1], 1, &[(1, 0), (2, 0)]);
+        assert_vias(&r, &vias, &[(1, 0)]);
+        assert!(vias.iter().all(|v| v.net_id == n));
+    }
+
+    #[test]
+    fn create_path_multiple_vias() {
+        let (r, n) = router();
+        let path = [
+            state(0, 0, 0, n),
+            state(1, 0, 0, n),
+            state(1, 0, 1, n),
+            state(1, 1, 1, n),
+            state(1, 1, 0, n),
+            state(2, 1, 0, n),
+        ];
+        let (wires, vias) = r.create_path(&path).unwrap();
+        assert_eq!(wires.len(), 3);
+        assert_wire(&r, &wires[0], 0, &[(0, 0), (1, 0)]);
+        assert_wire(&r, &wires[1], 1, &[(1, 0), (1, 1)]);
+        assert_wire(&r, &wires[2], 0, &[(1, 1), (2, 1)]);
+        assert_vias(&r, &vias, &[(1, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn create_path_via_at_start() {
+        let (mut r, n) = router();
+        let path = [state(0, 0, 0, n), state(0, 0, 1, n), state(1, 0, 1, n)];
+        let (wires, vias) = r.create_path(&path).unwrap();
+        assert_eq!(wires.len(), 1);
+        assert_wire(&r, &wires[0], 1, &[(0, 0), (1, 0)]);
+        assert_vias(&r, &vias, &[(0, 0)]);
+
+        r.set_keep_zero_length_wires(true);
+        let (wires, vias) = r.create_path(&path).unwrap();
+        assert_eq!(wires.len(), 2);
+        assert_wire(&r, &wires[0], 0, &[(0, 0)]);
+        assert_wire(&r, &wires[1], 1, &[(0, 0), (1, 0)]);
+        assert_vias(&r, &vias, &[(0, 0)]);
+    }
+
+    #[test]
+    fn create_path_via_at_end() {
+        let (r, n) = router();
+        let path = [state(0, 0, 0, n), state(1, 0, 0, n), state(1, 0, 1, n)];
+        let (wires, vias) = r.create_path(&path).unwrap();
+        assert_eq!(wires.len(), 1);
+        assert_wire(&r, &wires[0], 0, &[(0, 0), (1, 0)]);
+        assert_vias(&r, &vias, &[(1, 0)]);
+    }
+
+    fn groups(wires: &[&[Pt]]) -> Vec<Vec<State>> {
+        let mut pcb = test_pcb();
+        let net_id = pcb.to_id("A");
+        for pts in wires {
+            let shape = LayerShape { layers: LayerSet::one(0), shape: path(pts, 0.1).shape() };
+            pcb.add_wire(Wire { shape, net_id });
+        }
+        let r = GridRouter::new(pcb, vec![net_id]);
+        let net = r.place.pcb().net(net_id).unwrap().clone();
+        let states = net.pins.iter().map(|p| r.pin_ref_state(p)).collect::<Result<Vec<_>>>();
+        r.connected_groups(&net, states.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn connected_groups_unconnected() {
+        assert_eq!(groups(&[]).len(), 2);
+    }
+
+    #[test]
+    fn connected_groups_wire_chain() {
+        // R1's pin is at (5, 11) and R2's is at (16, 10).
+        let a: &[Pt] = &[pt(5.0, 11.0), pt(10.0, 11.0)];
+        let b: &[Pt] = &[pt(10.0, 11.0), pt(12.0, 11.0)];
+        let c: &[Pt] = &[pt(12.0, 11.0), pt(16.0, 10.0)];
+        // A wire touching a pin followed by one which only touches other wires
+        // must not make a wire the root of the pin's group.
+        assert_eq!(groups(&[a, b]).len(), 2);
+        let g = groups(&[a, b, c]);
+        assert_eq!(g.len(), 1);
+        assert_eq!(g[0].len(), 2);
+    }
+
+    // Routes nets A and B of the test board as a differential pair.
+    fn route_pair(gap: f64) -> (RouteResult, Id, Id) {
+        let mut pcb = test_pcb();
+        let (a, b) = (pcb.to_id("A"), pcb.to_id("B"));
+        pcb.add_diff_pair(a, b);
+        let mut r = GridRouter::new(pcb, vec![a, b]);
+        r.set_diff_pair_gap(gap);
+        (r.route().unwrap(), a, b)
+    }
+
+    #[test]
+    fn diff_pair_within_gap() {
+        // A and B's pins are at most 6 mm apart.
+        let (res, _, _) = route_pair(10.0);
+        assert!(!res.failed);
+        assert!(res.failed_nets.is_empty());
+    }
+
+    #[test]
+    fn diff_pair_outside_gap_fails_both_once() {
+        // B's pins are 5 mm from A's, so it can't stay within the gap.
+        let (res, a, b) = route_pair(0.5);
+        assert!(res.failed);
+        let mut failed = res.failed_nets;
+        failed.sort_unstable();
+        let mut expected = vec![a, b];
+        expected.sort_unstable();
+        assert_eq!(failed, expected);
+    }
+
+    fn wire_pts(res: &RouteResult) -> Vec<(Id, String)> {
+        res.wires.iter().map(|v| (v.net_id, format!("{:?}", v.shape))).collect()
+    }
+
+    #[test]
+    fn parallel_matches_sequential() {
+        // Nets A and B are far enough apart to be routed in the same batch.
+        let pcb = test_pcb();
+        let order = vec![pcb.to_id("A"), pcb.to_id("B")];
+        let mut seq = GridRouter::new(pcb.clone(), order.clone());
+        let seq = seq.route().unwrap();
+        let mut par = GridRouter::new(pcb, order);
+        assert_eq!(par.net_batches().unwrap().len(), 1);
+        par.set_parallel(true);
+        let par = par.route().unwrap();
+        assert!(!seq.failed && !par.failed);
+        assert_eq!(wire_pts(&seq), wire_pts(&par));
+    }
+
+    #[test]
+    fn heuristic_is_in_grid_steps() {
+        let pcb = test_pcb();
+        let n = pcb.to_id("A");
+        for resolution in [0.1, 0.4, 2.0] {
+            let r = GridRouter::with_resolution(pcb.clone(), vec![n], resolution).unwrap();
+            let dsts = [state(0, 0, 0, n), state(20, 0, 0, n)];
+            // Ten steps away from the nearest destination, regardless of step size.
+            assert!(eq(r.heuristic(&dsts, pti(10, 0)), 10.0));
+            assert!(eq(r.heuristic(&dsts, pti(17, 4)), 5.0));
+        }
+    }
+
+    #[test]
+    fn reroute_net_keeps_other_nets_place_ids() {
+        let pcb = test_pcb();
+        let (a, b) = (pcb.to_id("A"), pcb.to_id("B"));
+        let mut r = GridRouter::new(pcb, vec![a, b]);
+        assert!(!r.route_net(a, false).unwrap().failed);
+        assert!(!r.route_net(b, false).unwrap().failed);
+        let before = r.place.net_place_ids(b).to_vec();
+        assert!(!before.is_empty());
+
+        assert!(!r.reroute_net(a).unwrap().failed);
+        assert_eq!(r.place.net_place_ids(b), before);
+        assert!(!r.place.net_place_ids(a).is_empty());
+    }
+
+    // Y coordinates of the points of net B's wires, routing nets A and B on a
+    // board where R1 and R2 are at (2, 10) and (18, 10), and R3 and R4 are
+    // at (2, 12) and (18, 12).
+    fn bus_ys(bus: bool) -> Vec<f64> {
+        let dsn = TEST_DSN
+            .replace("(place R1 5000 10000 front 90)", "(place R1 1000 10000 front 0)")
+            .replace("(place R2 15000 10000 front 0)", "(place R2 17000 10000 front 0)")
+            .replace("(place R3 5000 5000 front 0)", "(place R3 1000 12000 front 0)")
+            .replace("(place R4 15000 5000 front 0)", "(place R4 17000 12000 front 0)")
+            // Narrow enough that wires in adjacent grid cells keep clearance.
+            .replace("(width 200)", "(width 100)")
+            .replace("(width 400)", "(width 100)");
+        let pcb = load_dsn(&dsn).unwrap();
+        let (a, b) = (pcb.to_id("A"), pcb.to_id("B"));
+        let mut r = GridRouter::new(pcb, vec![a, b]);
+        if bus {
+            r.add_bus_group(vec![a, b]);
+        }
+        let res = r.route().unwrap();
+        assert!(!res.failed);
+        res.wires
+            .iter()
+            .filter(|w| w.net_id == b)
+            .flat_map(|w| {
+                let Shape::Path(p) = &w.shape.shape else { panic!("wire is not a path") };
+                p.pts().iter().map(|v| v.y).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bus_nets_route_together() {
+        // Without a bus, B runs straight across, 2 mm away from A.
+        let ys = bus_ys(false);
+        assert!(ys.iter().all(|&y| y > 11.5), "{ys:?}");
+        // With a bus, B moves next to A for most of the way.
+        let ys = bus_ys(true);
+        let near = ys.iter().filter(|&&y| y < 11.0).count();
+        assert!(2 * near > ys.len(), "{ys:?}");
+    }
+
+    // Routes net B, which runs along y = 5, through a wall with a slot at
+    // y = 5 which is too narrow for its normal width.
+    fn route_through_slot(neck_down: Option<f64>) -> RouteResult {
+        let mut pcb = test_pcb();
+        let b = pcb.to_id("B");
+        for r in [rt(10.0, 0.0, 10.4, 4.74), rt(10.0, 5.26, 10.4, 20.0)] {
+            pcb.add_keepout(Keepout {
+                kind: KeepoutType::Keepout,
+                shape: LayerShape { layers: pcb.copper_layers(), shape: r.shape() },
+                windows: Vec::new(),
+            });
+        }
+        let mut r = GridRouter::new(pcb, vec![b]);
+        r.set_neck_down_radius(neck_down);
+        r.route().unwrap()
+    }
+
+    #[test]
+    fn neck_down_through_slot() {
+        assert!(route_through_slot(None).failed);
+
+        let res = route_through_slot(Some(0.02));
+        assert!(!res.failed);
+        let radii: Vec<f64> = res
+            .wires
+            .iter()
+            .map(|w| {
+                let Shape::Path(p) = &w.shape.shape else { panic!("wire is not a path") };
+                p.r()
+            })
+            .collect();
+        assert!(radii.iter().any(|&r| eq(r, 0.02)), "{radii:?}");
+        assert!(radii.iter().any(|&r| eq(r, 0.1)), "{radii:?}");
+    }
+
+    #[test]
+    fn orthogonal_route_has_no_diagonals() {
+        let (mut r, net_id) = router();
+        r.set_routing_style(RoutingStyle::Orthogonal);
+        let res = r.route().unwrap();
+        assert!(!res.failed);
+        assert!(res.wires.iter().all(|w| w.net_id == net_id));
+        for wire in &res.wires {
+            let Shape::Path(p) = &wire.shape.shape else { panic!("wire is not a path") };
+            for v in p.pts().windows(2) {
+                assert!(eq(v[0].x, v[1].x) || eq(v[0].y, v[1].y), "{v:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn route_points_open_board() {
+        let (mut r, net_id) = router();
+        let (a, b) = (pt(2.0, 2.0), pt(8.0, 6.0));
+        let wires = r.route_points(a, b, LayerSet::one(0), net_id).unwrap();
+        assert_eq!(wires.len(), 1);
+        let Shape::Path(p) = &wires[0].shape.shape else { panic!("wire is not a path") };
+        let pts = p.pts();
+        assert!(pt_eq(pts[0], r.world_pt_mid(r.grid_pt(a))), "{pts:?}");
+        assert!(pt_eq(pts[pts.len() - 1], r.world_pt_mid(r.grid_pt(b))), "{pts:?}");
+        // Each step goes to a neighbouring grid cell.
+        for v in pts.windows(2) {
+            assert!(v[0].dist(v[1]) <= 2.0_f64.sqrt() * r.resolution + 1e-6, "{v:?}");
+        }
+        // Nothing was added to the place model.
+        assert!(r.place.net_place_ids(net_id).is_empty());
+    }
+
+    #[test]
+    fn congestion_higher_at_pad() {
+        let (r, _) = router();
+        let map = r.congestion_map().unwrap();
+        // R2's pad is at (16, 10) on F.Cu only, so at most half the layers are blocked.
+        let pad = map[&r.grid_pt(pt(16.0, 10.0))];
+        let open = map[&r.grid_pt(pt(10.0, 15.0))];
+        assert!(eq(open, 0.0), "{open}");
+        assert!(pad > open && pad <= 0.5, "{pad}");
+    }
+
+    #[test]
+    fn create_path_via_only_state() {
+        // Going down and straight back up leaves a single state on layer 1.
+        let (mut r, n) = router();
+        let path = [
+            state(0, 0, 0, n),
+            state(1, 0, 0, n),
+            state(1, 0, 1, n),
+            state(1, 0, 0, n),
+            state(2, 0, 0, n),
+        ];
+        let (wires, vias) = r.create_path(&path).unwrap();
+        assert_eq!(wires.len(), 2);
+        assert_wire(&r, &wires[0], 0, &[(0, 0), (1, 0)]);
+        assert_wire(&r, &wires[1], 0, &[(1, 0), (2, 0)]);
+        assert_vias(&r, &vias, &[(1, 0), (1, 0)]);
+
+        r.set_keep_zero_length_wires(true);
+        let (wires, vias) = r.create_path(&path).unwrap();
+        assert_eq!(wires.len(), 3);
+        assert_wire(&r, &wires[1], 1, &[(1, 0)]);
+        assert_vias(&r, &vias, &[(1, 0), (1, 0)]);
+    }
+
+    #[test]
+    fn fixed_wiring_survives_route() {
+        // Net B has a protected wire on F.Cu across the way between A's pins.
+        let dsn = TEST_DSN.replace(
+            "(wiring)",
+            "(wiring
+    (wire (path F.Cu 200 6000 5000 11000 5000 11000 15000) (net B) (type protect))
+  )",
+        );
+        let pcb = load_dsn(&dsn).unwrap();
+        let (a, b) = (pcb.to_id("A"), pcb.to_id("B"));
+        assert!(pcb.net(b).unwrap().is_fixed());
+        assert!(!pcb.net(a).unwrap().is_fixed());
+        let mut r = GridRouter::new(pcb.clone(), vec![a, b]);
+        let res = r.route().unwrap();
+        assert!(!res.failed, "{:?}", res.failed_nets);
+        assert!(res.wires.iter().all(|v| v.net_id == a));
+        assert!(res.validate(&pcb).is_empty());
+        assert!(r.reroute_net(b).is_err());
+        assert_eq!(r.place.pcb().net_wires(b).count(), 1);
+    }
+
+    #[test]
+    fn mask_layer_not_routed() {
+        let dsn = TEST_DSN
+            .replace(
+                "(layer B.Cu (type signal))",
+                "(layer B.Cu (type signal))\n    (layer F.Mask (type mask))",
+            )
+            .replace(
+                "(circle F.Cu 1000))",
+                "(circle F.Cu 1000))\n      (shape (circle F.Mask 1200))",
+            );
+        let pcb = load_dsn(&dsn).unwrap();
+        let order = vec![pcb.to_id("A"), pcb.to_id("B")];
+        let res = GridRouter::new(pcb, order).route().unwrap();
+        assert!(!res.failed, "{:?}", res.failed_nets);
+        assert!(res.wires.iter().all(|v| !v.shape.layers.contains(2)));
+    }
+
+    #[test]
+    fn use_layer_keeps_net_on_layer() {
+        // A wall on F.Cu between A's pins, which A has to go around instead of
+        // using vias.
+        let dsn = TEST_DSN
+            .replace("(circuit (use_via via600))", "(circuit (use_via via600) (use_layer F.Cu))")
+            .replace(
+                "(via via600)",
+                "(via via600)\n    (keepout (rect F.Cu 10500 0 11500 17000))",
+            );
+        let pcb = load_dsn(&dsn).unwrap();
+        let a = pcb.to_id("A");
+        assert_eq!(pcb.net_ruleset(a).allowed_layers(), Some(LayerSet::one(0)));
+        let res = GridRouter::new(pcb, vec![a]).route().unwrap();
+        assert!(!res.failed);
+        assert!(res.vias.is_empty());
+        assert!(!res.wires.is_empty());
+        assert!(res.wires.iter().all(|v| v.shape.layers == LayerSet::one(0)));
+    }
+
+    #[test]
+    fn dsn_wire_grid_sets_resolution() {
+        let dsn = TEST_DSN.replace("(via via600)", "(via via600)\n    (grid wire 250)");
+        let pcb = load_dsn(&dsn).unwrap();
+        assert_eq!(pcb.wire_grid(), Some(0.25));
+        let net_id = pcb.to_id("A");
+        let mut r = GridRouter::new(pcb, vec![net_id]);
+        assert!(eq(r.resolution, 0.25));
+        let res = r.route().unwrap();
+        assert!(!res.failed);
+        assert!(load_dsn(&TEST_DSN.replace("(via via600)", "(via via600) (grid wire 0)")).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use ahash::HashMap;
+use memegeom::geom::math::pt_eq;
+use memegeom::primitive::point::Pt;
+use memegeom::primitive::shape::Shape;
+use memegeom::primitive::{path, ShapeOps};
+
+use crate::model::pcb::{Pcb, Wire};
+use crate::name::Id;
+use crate::route::place_model::PlaceModel;
+use crate::route::router::{apply_route_result, RouteResult};
+
+// A group of nets which should have lengths within |tolerance| of each other.
+#[must_use]
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MatchGroup {
+    pub nets: Vec<Id>,
+    pub tolerance: f64,
+}
+
+// Lengthens the shorter nets in |group| up to the length of the longest net
+// by adding meanders to their longest wire segment. Meander bumps which would
+// collide with anything on |pcb| or in |res| are skipped, so a net may end up
+// shorter than requested if there isn't room.
+pub fn match_lengths(pcb: &Pcb, res: &mut RouteResult, group: &MatchGroup) {
+    let mut lengths: HashMap<Id, f64> = group.nets.iter().map(|&id| (id, 0.0)).collect();
+    for wire in &res.wires {
+        if let Some(l) = lengths.get_mut(&wire.net_id) {
+            *l += wire.length();
+        }
+    }
+    let longest = lengths.values().copied().fold(0.0, f64::max);
+
+    let mut board = pcb.clone();
+    apply_route_result(&mut board, res);
+    let mut place = PlaceModel::new(board);
+
+    for net_id in &group.nets {
+        let extra = longest - lengths[net_id];
+        if extra <= group.tolerance {
+            continue;
+        }
+        // Pick the longest segment in the net to put the meander in.
+        let mut best = None;
+        let mut best_len = 0.0;
+        for (idx, wire) in res.wires.iter().enumerate() {
+            if wire.net_id != *net_id {
+                continue;
+            }
+            if let Shape::Path(p) = &wire.shape.shape {
+                for (seg, v) in p.pts().windows(2).enumerate() {
+                    let l = v[0].dist(v[1]);
+                    if l > best_len {
+                        best_len = l;
+                        best = Some((idx, seg));
+                    }
+                }
+            }
+        }
+        if let Some((idx, seg)) = best {
+            if let Some(wire) = meander(&place, &res.wires[idx], seg, extra) {
+                place.add_wire(&wire);
+                res.wires[idx] = wire;
+            }
+        }
+    }
+}
+
+// Returns |wire| with segment |seg| replaced by a meander which is |extra|
+// longer, or None if no bump fits.
+fn meander(place: &PlaceModel, wire: &Wire, seg: usize, extra: f64) -> Option<Wire> {
+    let Shape::Path(p) = &wire.shape.shape else { return None };
+    let layer = wire.shape.layers.iter().next()?;
+    let r = p.r();
+    let mut pts = p.pts().to_vec();
+    let (p0, p1) = (pts[seg], pts[seg + 1]);
+    let l = p0.dist(p1);
+    let dir = (1.0 / l) * (p1 - p0);
+    let perp = dir.perp();
+
+    // Split the segment into 2n parts, with a bump of height h on every other
+    // part. Each bump adds 2h length. Keep bumps far enough apart to not overlap.
+    // Bumps which are blocked on both sides are dropped, and the rest are made
+    // taller to make up the length, until every remaining bump fits.
+    let n = ((l / (8.0 * r)).floor() as usize).max(1);
+    let s = l / (2 * n) as f64;
+    let mut slots: Vec<usize> = (0..n).collect();
+    let bumps = loop {
+        if slots.is_empty() {
+            return None;
+        }
+        let h = extra / (2 * slots.len()) as f64;
+        let mut bumps: Vec<[Pt; 4]> = Vec::with_capacity(slots.len());
+        let mut blocked = None;
+        for (i, &slot) in slots.iter().enumerate() {
+            let base = p0 + (2 * slot) as f64 * s * dir;
+            let free = [h, -h]
+                .into_iter()
+                .map(|d| [base, base + d * perp, base + s * dir + d * perp, base + s * dir])
+                .find(|b| {
+                    !place.is_wire_blocked(&place.create_wire_radius(wire.net_id, layer, b, r))
+                });
+            if let Some(b) = free {
+                bumps.push(b);
+            } else {
+                blocked = Some(i);
+                break;
+            }
+        }
+        match blocked {
+            Some(i) => {
+                slots.remove(i);
+            }
+            None => break bumps,
+        }
+    };
+
+    // The first bump may start at |p0|, which is already in the path.
+    let bumps: Vec<Pt> = bumps.into_iter().flatten().filter(|&v| !pt_eq(v, p0)).collect();
+    pts.splice(seg + 1..seg + 1, bumps);
+    Some(Wire { shape: path(&pts, r).shape(), ..wire.clone() })
+}
+
+#[cfg(test)]
+mod tests {
+    use memegeom::geom::math::eq;
+    use memegeom::primitive::rt;
+
+    use super::*;
+    use crate::model::pcb::{Keepout, KeepoutType, LayerSet, LayerShape};
+    use crate::route::grid::GridRouter;
+    use crate::testing::test_pcb;
+
+    fn net_length(res: &RouteResult, net_id: Id) -> f64 {
+        res.wires.iter().filter(|w| w.net_id == net_id).map(Wire::length).sum()
+    }
+
+    fn routed() -> (Pcb, RouteResult, Id, Id) {
+        let pcb = test_pcb();
+        let (a, b) = (pcb.to_id("A"), pcb.to_id("B"));
+        let res = GridRouter::new(pcb.clone(), vec![a, b]).route().unwrap();
+        assert!(!res.failed);
+        (pcb, res, a, b)
+    }
+
+    #[test]
+    fn match_lengths_lengthens_shorter_net() {
+        let (pcb, mut res, a, b) = routed();
+        let (len_a, len_b) = (net_length(&res, a), net_length(&res, b));
+        assert!(len_a > len_b + 0.5, "{len_a} {len_b}");
+
+        match_lengths(&pcb, &mut res, &MatchGroup { nets: vec![a, b], tolerance: 0.05 });
+        assert!(eq(net_length(&res, a), len_a));
+        assert!((net_length(&res, b) - len_a).abs() <= 0.05, "{}", net_length(&res, b));
+        assert!(res.validate(&pcb).is_empty());
+    }
+
+    #[test]
+    fn match_lengths_skips_blocked_bumps() {
+        let (mut pcb, mut res, a, b) = routed();
+        let len_b = net_length(&res, b);
+
+        // Net B runs along y = 5 between R3 and R4. Hem it in on both sides so
+        // no bump has room.
+        for r in [rt(7.0, 5.12, 15.0, 6.0), rt(7.0, 4.0, 15.0, 4.88)] {
+            pcb.add_keepout(Keepout {
+                kind: KeepoutType::Keepout,
+                shape: LayerShape { layers: LayerSet::one(0), shape: r.shape() },
+                windows: Vec::new(),
+            });
+        }
+        match_lengths(&pcb, &mut res, &MatchGroup { nets: vec![a, b], tolerance: 0.05 });
+        assert!(eq(net_length(&res, b), len_b));
+    }
+}
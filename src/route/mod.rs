@@ -1,3 +1,9 @@
+pub mod analysis;
+pub mod arcs;
+pub mod drc;
+pub mod escape;
 pub mod grid;
+pub mod length_match;
 pub mod place_model;
 pub mod router;
+pub mod shield;
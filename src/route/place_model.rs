@@ -1,3 +1,5 @@
+use std::cell::Cell;
+
 use ahash::HashMap;
 use eyre::Result;
 use memegeom::geom::math::le;
@@ -6,17 +8,19 @@ use memegeom::geom::qt::query::{Kinds, KindsQuery, Query, ShapeInfo, Tag, TagQue
 use memegeom::primitive::compound::Compound;
 use memegeom::primitive::point::Pt;
 use memegeom::primitive::rect::Rt;
-use memegeom::primitive::{path, ShapeOps};
+use memegeom::primitive::{path, pt, ShapeOps};
 use memegeom::tf::Tf;
 
 use crate::model::pcb::{
-    Clearance, LayerId, LayerSet, LayerShape, Net, ObjectKind, Padstack, Pcb, Pin, PinRef, Via,
-    Wire,
+    Clearance, Keepout, LayerId, LayerSet, LayerShape, Net, ObjectKind, Padstack, Pcb, Pin, PinRef,
+    Via, Wire,
 };
 use crate::name::Id;
 
 pub type PlaceId = (LayerId, ShapeIdx);
 
+const MAX_FREE_RINGS: i64 = 32; // Limit on how far |nearest_free| searches, in rings.
+
 // Need to handle:
 // but also keeping them for hole drils
 #[must_use]
@@ -26,8 +30,15 @@ pub struct PlaceModel {
     // TODO: Can move layerids to quadtree?
     boundary: HashMap<LayerId, Compound>,
     blocked: HashMap<LayerId, Compound>,
+    // Keepouts with windows, in world space. These can't be represented in |blocked|.
+    windowed_keepouts: Vec<Keepout>,
     pins: HashMap<PinRef, Vec<PlaceId>>, // Record which pins correspond to which place ids in |blocked|.
+    routing: HashMap<Id, Vec<PlaceId>>, // Record which wires and vias belong to which net in |blocked|.
     bounds: Rt,
+    // Memoized distances between pairs of shapes in |blocked|. Cleared when geometry changes.
+    clearance_cache: HashMap<(PlaceId, PlaceId), f64>,
+    clearance_cache_hits: usize,
+    query_count: Cell<usize>, // Number of collision queries made, for performance tests.
 }
 
 impl PlaceModel {
@@ -36,8 +47,13 @@ impl PlaceModel {
             pcb: Pcb::default(), // Initially set as empty since we will initialise.
             boundary: HashMap::default(),
             blocked: HashMap::default(),
+            windowed_keepouts: Vec::new(),
             pins: HashMap::default(),
+            routing: HashMap::default(),
             bounds: Rt::empty(),
+            clearance_cache: HashMap::default(),
+            clearance_cache_hits: 0,
+            query_count: Cell::new(0),
         };
         m.init(pcb);
         m
@@ -53,22 +69,33 @@ impl PlaceModel {
     }
 
     // Creates a wire for a given net, but doesn't add it.
-    pub fn create_wire(&self, net_id: Id, layer: LayerId, pts: &[Pt]) -> Wire {
-        let rs = self.pcb.net_ruleset(net_id);
-        let shape =
-            LayerShape { layers: LayerSet::one(layer), shape: path(pts, rs.radius()).shape() };
+    pub fn create_wire(&self, net_id: Id, layer: LayerId, pts: &[Pt]) -> Result<Wire> {
+        Ok(self.create_wire_radius(net_id, layer, pts, self.pcb.net_radius(net_id, layer)?))
+    }
+
+    // Creates a wire with the given radius instead of the net's radius, but doesn't add it.
+    pub fn create_wire_radius(&self, net_id: Id, layer: LayerId, pts: &[Pt], r: f64) -> Wire {
+        let shape = LayerShape { layers: LayerSet::one(layer), shape: path(pts, r).shape() };
         Wire { shape, net_id }
     }
 
     pub fn add_wire(&mut self, wire: &Wire) -> Vec<PlaceId> {
-        Self::add_shape(
+        self.clearance_cache.clear();
+        let ids = Self::add_shape(
             self.bounds,
             &mut self.blocked,
             &Tf::identity(),
             &wire.shape,
             Tag(wire.net_id),
             ObjectKind::Wire.query(),
-        )
+        );
+        self.routing.entry(wire.net_id).or_default().extend(&ids);
+        ids
+    }
+
+    // Removes a wire, given the ids returned when it was added.
+    pub fn remove_wire(&mut self, ids: &[PlaceId]) {
+        self.remove_routing(ids);
     }
 
     // Creates a via for a given net, but doesn't add it.
@@ -78,11 +105,31 @@ impl PlaceModel {
     }
 
     pub fn add_via(&mut self, via: &Via) -> Vec<PlaceId> {
-        self.add_padstack(&via.tf(), &via.padstack, Tag(via.net_id), ObjectKind::Via.query())
+        self.clearance_cache.clear();
+        let ids =
+            self.add_padstack(&via.tf(), &via.padstack, Tag(via.net_id), ObjectKind::Via.query());
+        self.routing.entry(via.net_id).or_default().extend(&ids);
+        ids
+    }
+
+    // Removes a via, given the ids returned when it was added.
+    pub fn remove_via(&mut self, ids: &[PlaceId]) {
+        self.remove_routing(ids);
+    }
+
+    fn remove_routing(&mut self, ids: &[PlaceId]) {
+        self.clearance_cache.clear();
+        for &id in ids {
+            self.remove_shape(id);
+        }
+        for v in self.routing.values_mut() {
+            v.retain(|id| !ids.contains(id));
+        }
     }
 
     // Adds all pins in the given net.
     pub fn add_net(&mut self, pcb: &Pcb, net: &Net) -> Result<()> {
+        self.clearance_cache.clear();
         for p in &net.pins {
             let (component, pin) = pcb.pin_ref(p)?;
             self.add_pin(&component.tf(), p.clone(), pin, Tag(net.id));
@@ -92,11 +139,112 @@ impl PlaceModel {
 
     // Removes all pins in the given net.
     pub fn remove_net(&mut self, net: &Net) {
+        self.clearance_cache.clear();
         for p in &net.pins {
             self.remove_pin(p);
         }
     }
 
+    // Returns the distance between two placed shapes. Results are cached until
+    // geometry is added or removed, since DRC tends to query the same pairs.
+    pub fn clearance_between(&mut self, a: PlaceId, b: PlaceId) -> f64 {
+        let key = if a <= b { (a, b) } else { (b, a) };
+        if let Some(&d) = self.clearance_cache.get(&key) {
+            self.clearance_cache_hits += 1;
+            return d;
+        }
+        let shape_a = self.blocked.get(&a.0).unwrap().quadtree().shapes()[a.1].shape();
+        let shape_b = self.blocked.get(&b.0).unwrap().quadtree().shapes()[b.1].shape();
+        let d = shape_a.dist_to_shape(shape_b);
+        self.clearance_cache.insert(key, d);
+        d
+    }
+
+    #[must_use]
+    pub fn clearance_cache_hits(&self) -> usize {
+        self.clearance_cache_hits
+    }
+
+    // Place ids of the shapes of the given pin, if it was placed.
+    #[must_use]
+    pub fn pin_place_ids(&self, p: &PinRef) -> &[PlaceId] {
+        self.pins.get(p).map_or(&[], Vec::as_slice)
+    }
+
+    // Place ids of the wires and vias of the given net.
+    #[must_use]
+    pub fn net_place_ids(&self, net_id: Id) -> &[PlaceId] {
+        self.routing.get(&net_id).map_or(&[], Vec::as_slice)
+    }
+
+    // Removes all wires and vias in the given net.
+    pub fn remove_net_routing(&mut self, net_id: Id) {
+        self.clearance_cache.clear();
+        if let Some(ids) = self.routing.remove(&net_id) {
+            for id in ids {
+                self.remove_shape(id);
+            }
+        }
+    }
+
+    // Changes the wire radius of the given net. Existing wires are unchanged,
+    // so the net should be rerouted afterwards.
+    pub fn set_net_radius(&mut self, net_id: Id, radius: f64) {
+        self.clearance_cache.clear();
+        self.pcb.set_net_radius(net_id, radius);
+    }
+
+    // Number of collision queries made against the quadtrees so far.
+    #[must_use]
+    pub fn query_count(&self) -> usize {
+        self.query_count.get()
+    }
+
+    fn count_query(&self) {
+        self.query_count.set(self.query_count.get() + 1);
+    }
+
+    // Finds the nearest point to |p| within |max_radius| where a wire of the given
+    // net could be placed, searching outwards in rings of cells the size of the wire.
+    pub fn nearest_free(
+        &self,
+        p: Pt,
+        layer: LayerId,
+        net_id: Id,
+        max_radius: f64,
+    ) -> Result<Option<Pt>> {
+        let step = self.pcb.net_radius(net_id, layer)?;
+        if !step.is_finite() || step <= 0.0 || !max_radius.is_finite() {
+            return Ok(None);
+        }
+        let rings = ((max_radius / step).ceil() as i64).min(MAX_FREE_RINGS);
+        for k in 0..=rings {
+            let mut best: Option<Pt> = None;
+            for x in -k..=k {
+                for y in -k..=k {
+                    // Only look at the boundary of the ring.
+                    if x.abs() != k && y.abs() != k {
+                        continue;
+                    }
+                    let q = p + pt(x as f64 * step, y as f64 * step);
+                    if q.dist(p) > max_radius {
+                        continue;
+                    }
+                    if self.is_wire_blocked(&self.create_wire(net_id, layer, &[q])?) {
+                        continue;
+                    }
+                    if best.map_or(true, |b| q.dist(p) < b.dist(p)) {
+                        best = Some(q);
+                    }
+                }
+            }
+            if best.is_some() {
+                return Ok(best);
+            }
+        }
+        Ok(None)
+    }
+
     pub fn is_wire_blocked(&self, wire: &Wire) -> bool {
         self.is_shape_blocked(
             &Tf::identity(),
@@ -117,6 +265,18 @@ impl PlaceModel {
         )
     }
 
+    // Like |is_via_blocked|, but ignores objects on the via's own net. Useful
+    // for checking vias after the rest of their net has been placed.
+    pub fn is_via_blocked_by_other_nets(&self, via: &Via) -> bool {
+        self.is_padstack_blocked(
+            &via.tf(),
+            &via.padstack,
+            TagQuery::Except(Tag(via.net_id)),
+            ObjectKind::Via,
+            self.pcb.net_ruleset(via.net_id).clearances(),
+        )
+    }
+
     pub fn is_shape_blocked(
         &self,
         tf: &Tf,
@@ -127,28 +287,46 @@ impl PlaceModel {
     ) -> bool {
         let s = tf.shape(&ls.shape);
 
+        // Boundaries are per layer. If there are boundaries, but none on this layer,
+        // then the board doesn't exist on this layer.
         for layer in ls.layers.iter() {
             if let Some(boundary) = self.boundary.get(&layer) {
                 // TODO: Convert boundary to path and compute distance to it for clearance.
+                self.count_query();
                 if !boundary.contains(&s, Query(q, KindsQuery::All)) {
                     return true;
                 }
+            } else if !self.boundary.is_empty() {
+                return true;
             }
         }
 
         // Check for intersection first, it's generally cheaper than checking distance.
         for layer in ls.layers.iter() {
             if let Some(blocked) = self.blocked.get(&layer) {
+                self.count_query();
                 if blocked.intersects(&s, Query(q, KindsQuery::All)) {
                     return true;
                 }
             }
         }
 
+        // Blocked by a keepout unless entirely inside one of its windows.
+        for k in &self.windowed_keepouts {
+            self.count_query();
+            if !(k.shape.layers & ls.layers).is_empty()
+                && k.shape.shape.intersects_shape(&s)
+                && !k.windows.iter().any(|w| w.contains_shape(&s))
+            {
+                return true;
+            }
+        }
+
         // Check for clearance.
         for layer in ls.layers.iter() {
             if let Some(blocked) = self.blocked.get(&layer) {
                 for c in clearances {
+                    self.count_query();
                     let d = blocked.dist(&s, Query(q, KindsQuery::HasCommon(c.subset_for(kind))));
                     if le(d, c.amount()) {
                         return true;
@@ -163,7 +341,7 @@ impl PlaceModel {
     fn init(&mut self, pcb: Pcb) {
         let tf = Tf::identity();
 
-        self.bounds = self.bounds.united(&pcb.bounds());
+        self.bounds = self.bounds.united(&pcb.content_bounds());
         for boundary in pcb.boundaries() {
             Self::add_shape(
                 self.bounds,
@@ -182,14 +360,7 @@ impl PlaceModel {
             self.add_via(via);
         }
         for keepout in pcb.keepouts() {
-            Self::add_shape(
-                self.bounds,
-                &mut self.blocked,
-                &tf,
-                &keepout.shape,
-                NO_TAG,
-                ObjectKind::Area.query(),
-            );
+            self.add_keepout(&tf, keepout);
         }
 
         for c in pcb.components() {
@@ -200,14 +371,7 @@ impl PlaceModel {
                 self.add_pin(&tf, r, pin, tag);
             }
             for keepout in &c.keepouts {
-                Self::add_shape(
-                    self.bounds,
-                    &mut self.blocked,
-                    &tf,
-                    &keepout.shape,
-                    NO_TAG,
-                    ObjectKind::Area.query(),
-                );
+                self.add_keepout(&tf, keepout);
             }
         }
         self.pcb = pcb;
@@ -237,6 +401,26 @@ impl PlaceModel {
         idxs
     }
 
+    fn add_keepout(&mut self, tf: &Tf, keepout: &Keepout) {
+        if keepout.windows.is_empty() {
+            Self::add_shape(
+                self.bounds,
+                &mut self.blocked,
+                tf,
+                &keepout.shape,
+                NO_TAG,
+                ObjectKind::Area.query(),
+            );
+        } else {
+            let shape = tf.shape(&keepout.shape.shape);
+            self.windowed_keepouts.push(Keepout {
+                kind: keepout.kind.clone(),
+                shape: LayerShape { layers: keepout.shape.layers, shape },
+                windows: keepout.windows.iter().map(|w| tf.shape(w)).collect(),
+            });
+        }
+    }
+
     fn add_padstack(
         &mut self,
         tf: &Tf,
@@ -285,3 +469,77 @@ impl PlaceModel {
         padstack.shapes.iter().any(|shape| self.is_shape_blocked(tf, shape, q, kind, clearances))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use memegeom::geom::math::eq;
+    use memegeom::primitive::rt;
+
+    use super::*;
+    use crate::dsn::load_dsn;
+    use crate::model::pcb::KeepoutType;
+    use crate::testing::{test_pcb, TEST_DSN};
+
+    #[test]
+    fn nearest_free_around_blocked_pin_center() {
+        let mut pcb = test_pcb();
+        let net_id = pcb.to_id("A");
+        // Block the center of R2's pin, which is at (16, 10).
+        let shape = rt(15.9, 9.9, 16.1, 10.1).shape();
+        let shape = LayerShape { layers: pcb.copper_layers(), shape };
+        pcb.add_keepout(Keepout { kind: KeepoutType::Keepout, shape, windows: Vec::new() });
+        let place = PlaceModel::new(pcb);
+        let p = pt(16.0, 10.0);
+        assert!(place.is_wire_blocked(&place.create_wire(net_id, 0, &[p]).unwrap()));
+
+        let free = place.nearest_free(p, 0, net_id, 1.0).unwrap().unwrap();
+        assert!(free.dist(p) > 0.0 && free.dist(p) <= 1.0, "{free:?}");
+        assert!(!place.is_wire_blocked(&place.create_wire(net_id, 0, &[free]).unwrap()));
+        // Nothing is free that close.
+        assert_eq!(place.nearest_free(p, 0, net_id, 0.1).unwrap(), None);
+    }
+
+    #[test]
+    fn clearance_cache_hits_and_invalidation() {
+        let pcb = test_pcb();
+        let pin = |c: &str| PinRef { component: pcb.to_id(c), pin: pcb.to_id("1") };
+        let (r1, r2) = (pin("R1"), pin("R2"));
+        let mut place = PlaceModel::new(pcb.clone());
+        let (a, b) = (place.pin_place_ids(&r1)[0], place.pin_place_ids(&r2)[0]);
+
+        // R1's and R2's pads have radius 0.5 at (5, 11) and (16, 10).
+        let d = place.clearance_between(a, b);
+        assert!(eq(d, 122.0_f64.sqrt() - 1.0), "{d}");
+        assert_eq!(place.clearance_cache_hits(), 0);
+        assert!(eq(place.clearance_between(b, a), d));
+        assert_eq!(place.clearance_cache_hits(), 1);
+
+        // Adding geometry invalidates the cache.
+        let wire = place.create_wire(pcb.to_id("B"), 0, &[pt(1.0, 1.0), pt(2.0, 1.0)]).unwrap();
+        place.add_wire(&wire);
+        assert!(eq(place.clearance_between(a, b), d));
+        assert_eq!(place.clearance_cache_hits(), 1);
+    }
+
+    #[test]
+    fn query_count_increases() {
+        let pcb = test_pcb();
+        let net_id = pcb.to_id("A");
+        let place = PlaceModel::new(pcb);
+        let wire = place.create_wire(net_id, 0, &[pt(9.0, 15.0), pt(11.0, 15.0)]).unwrap();
+        let before = place.query_count();
+        assert!(!place.is_wire_blocked(&wire));
+        assert!(place.query_count() > before);
+    }
+
+    #[test]
+    fn layer_without_boundary_is_blocked() {
+        let dsn = TEST_DSN.replace("(rect pcb 0 0 20000 20000)", "(rect F.Cu 0 0 20000 20000)");
+        let pcb = load_dsn(&dsn).unwrap();
+        let net_id = pcb.to_id("A");
+        let place = PlaceModel::new(pcb);
+        let pts = [pt(9.0, 15.0), pt(11.0, 15.0)];
+        assert!(!place.is_wire_blocked(&place.create_wire(net_id, 0, &pts).unwrap()));
+        assert!(place.is_wire_blocked(&place.create_wire(net_id, 1, &pts).unwrap()));
+    }
+}
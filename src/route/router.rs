@@ -17,8 +17,10 @@ use memegeom::primitive::rect::Rt;
 use rand::prelude::SliceRandom;
 use rand::Rng;
 
+use crate::error::MemerouteError;
 use crate::model::pcb::{Pcb, Via, Wire};
 use crate::name::Id;
+use crate::route::drc::{route_violations, Violation};
 use crate::route::grid::GridRouter;
 
 pub trait RouteStrategy {
@@ -32,6 +34,7 @@ pub struct RouteResult {
     pub vias: Vec<Via>,
     pub debug_rts: Vec<Rt>,
     pub failed: bool,
+    pub failed_nets: Vec<Id>,
 }
 
 impl RouteResult {
@@ -40,39 +43,123 @@ impl RouteResult {
         self.vias.extend(r.vias);
         self.debug_rts.extend(r.debug_rts);
         self.failed |= r.failed;
+        for net_id in r.failed_nets {
+            if !self.failed_nets.contains(&net_id) {
+                self.failed_nets.push(net_id);
+            }
+        }
+    }
+
+    // Checks the result against the rules of |pcb|, returning any violations.
+    #[must_use]
+    pub fn validate(&self, pcb: &Pcb) -> Vec<Violation> {
+        route_violations(pcb, self)
+    }
+
+    pub fn stats(&self, pcb: &Pcb) -> RouteStats {
+        let total_nets = pcb.nets().filter(|v| !v.is_fixed()).count();
+        let completed_nets = total_nets.saturating_sub(self.failed_nets.len());
+        let total_length = self.wires.iter().map(Wire::length).sum();
+        let completion_ratio =
+            if total_nets == 0 { 1.0 } else { completed_nets as f64 / total_nets as f64 };
+        RouteStats {
+            total_nets,
+            completed_nets,
+            vias: self.vias.len(),
+            total_length,
+            completion_ratio,
+        }
     }
 }
 
+// Summary of the quality of a route result.
+#[must_use]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct RouteStats {
+    pub total_nets: usize,
+    pub completed_nets: usize,
+    pub vias: usize,
+    pub total_length: f64, // Total length of wires, in mm.
+    pub completion_ratio: f64,
+}
+
 #[must_use]
 #[derive(Debug)]
 pub struct Router {
     pcb: Mutex<Pcb>,
+    // Fitness and failed nets of the best net order evaluated so far.
+    best: Mutex<Option<(f64, Vec<Id>)>>,
+    strict: bool, // Whether failing to route any net is an error.
 }
 
 impl Clone for Router {
     fn clone(&self) -> Self {
-        Self::new(self.pcb.lock().unwrap().clone())
+        let mut r = Self::new(self.pcb.lock().unwrap().clone());
+        r.strict = self.strict;
+        r
     }
 }
 
 impl Router {
     pub fn new(pcb: Pcb) -> Self {
-        Self { pcb: Mutex::new(pcb) }
+        Self { pcb: Mutex::new(pcb), best: Mutex::new(None), strict: false }
+    }
+
+    // If set, routing returns a RoutingError listing the failed nets when any
+    // net can't be routed, instead of a partial result.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    // Returns ids of all nets which should be routed, i.e. excluding fixed nets.
+    pub fn routable_nets(&self) -> Vec<Id> {
+        self.pcb.lock().unwrap().nets().filter(|v| !v.is_fixed()).map(|v| v.id).collect()
     }
 
     pub fn rand_net_order(&self) -> Vec<Id> {
-        let mut net_order: Vec<_> = self.pcb.lock().unwrap().nets().map(|v| v.id).collect();
+        let mut net_order = self.routable_nets();
         //net_order.shuffle(rand::thread_rng());
         net_order.sort_unstable();
         net_order
     }
 
-    pub fn route(&self, net_order: Vec<Id>) -> Result<RouteResult> {
+    pub fn route(&self, net_order: Vec<Id>) -> std::result::Result<RouteResult, MemerouteError> {
+        let res = self.route_partial(net_order)?;
+        if self.strict && res.failed {
+            return Err(MemerouteError::RoutingError {
+                msg: "failed to route all nets".to_owned(),
+                failed_nets: res.failed_nets,
+            });
+        }
+        Ok(res)
+    }
+
+    // Routes in the given order, returning whatever could be routed.
+    fn route_partial(
+        &self,
+        net_order: Vec<Id>,
+    ) -> std::result::Result<RouteResult, MemerouteError> {
         let mut grid = GridRouter::new(self.pcb.lock().unwrap().clone(), net_order);
-        grid.route()
+        grid.route().map_err(routing_error)
     }
 
-    pub fn run_ga(&self) -> Result<RouteResult> {
+    // Moves the first net which failed to route in the best order so far to a
+    // random earlier position in the order, since nets routed earlier have less
+    // congestion to deal with. Nothing changes until an order with failed nets
+    // has been evaluated.
+    fn mutate_failed_earlier(&self, s: &mut RouteState) {
+        let failed = self.best.lock().unwrap().as_ref().and_then(|(_, v)| v.first().copied());
+        let Some(net_id) = failed else { return };
+        if let Some(idx) = s.iter().position(|&v| v == net_id) {
+            if idx > 0 {
+                let to = rand::thread_rng().gen_range(0..idx);
+                let id = s.remove(idx);
+                s.insert(to, id);
+            }
+        }
+    }
+
+    pub fn run_ga(&self) -> std::result::Result<RouteResult, MemerouteError> {
         let cfg = EvolveCfg::new(32)
             .set_mutation(Mutation::Adaptive)
             .set_crossover(Crossover::Adaptive)
@@ -84,7 +171,7 @@ impl Router {
             .set_par_fitness(true)
             .set_par_dist(true);
 
-        let net_order: Vec<_> = self.pcb.lock().unwrap().nets().map(|v| v.id).collect();
+        let net_order = self.routable_nets();
         let genfn = move || {
             let mut rand_order = net_order.clone();
             rand_order.shuffle(&mut rand::thread_rng());
@@ -95,7 +182,13 @@ impl Router {
         let mut trainer = Trainer::new(
             TrainerCfg::new("memeroute").set_termination(Termination::FixedGenerations(1)),
         );
-        let order = trainer.train(evolver, &EmptyDataSampler {})?.nth(0).state.0.clone();
+        let order = trainer
+            .train(evolver, &EmptyDataSampler {})
+            .map_err(routing_error)?
+            .nth(0)
+            .state
+            .0
+            .clone();
         self.route(order)
     }
 }
@@ -108,7 +201,7 @@ pub struct RouteState(pub Vec<Id>);
 impl Evaluator for Router {
     type State = RouteState;
     const NUM_CROSSOVER: usize = 4;
-    const NUM_MUTATION: usize = 4;
+    const NUM_MUTATION: usize = 5;
 
     fn crossover(&self, s1: &mut Self::State, s2: &mut Self::State, idx: usize) {
         match idx {
@@ -130,19 +223,25 @@ impl Evaluator for Router {
             1 => mutate_insert(s),
             2 => mutate_scramble(s),
             3 => mutate_inversion(s),
+            4 => self.mutate_failed_earlier(s),
             _ => panic!("unknown mutation strategy"),
         }
     }
 
     fn fitness(&self, s: &Self::State, _data: &Self::Data) -> Result<f64> {
-        let res = self.route(s.0.clone()).unwrap();
+        let res = self.route_partial(s.0.clone())?;
         let mut cost = 0.0;
         if res.failed {
             cost += 1000.0;
         }
         cost += res.vias.len() as f64 * 10.0;
         // TODO: Count wire lengths
-        Ok(1.0 / (1.0 + cost))
+        let fitness = 1.0 / (1.0 + cost);
+        let mut best = self.best.lock().unwrap();
+        if best.as_ref().map_or(true, |(f, _)| fitness > *f) {
+            *best = Some((fitness, res.failed_nets));
+        }
+        Ok(fitness)
     }
 
     fn distance(&self, s1: &Self::State, s2: &Self::State) -> Result<f64> {
@@ -150,6 +249,35 @@ impl Evaluator for Router {
     }
 }
 
+fn routing_error(e: eyre::Report) -> MemerouteError {
+    MemerouteError::from_report(e, |msg| MemerouteError::RoutingError {
+        msg,
+        failed_nets: Vec::new(),
+    })
+}
+
+// Changes the radius of the given net and reroutes just that net, leaving
+// other nets' routing alone. If the net can't be routed with the new radius,
+// |pcb| is left unchanged.
+pub fn reroute_net_with_radius(
+    pcb: &mut Pcb,
+    net_id: Id,
+    radius: f64,
+) -> std::result::Result<RouteResult, MemerouteError> {
+    let mut grid = GridRouter::new(pcb.clone(), vec![]);
+    let res = grid.set_net_radius(net_id, radius).map_err(routing_error)?;
+    if res.failed {
+        return Err(MemerouteError::RoutingError {
+            msg: format!("failed to reroute net {}", pcb.to_name(net_id)),
+            failed_nets: res.failed_nets,
+        });
+    }
+    pcb.set_net_radius(net_id, radius);
+    pcb.remove_net_routing(net_id);
+    apply_route_result(pcb, &res);
+    Ok(res)
+}
+
 pub fn apply_route_result(pcb: &mut Pcb, r: &RouteResult) {
     for wire in &r.wires {
         pcb.add_wire(wire.clone());
@@ -161,3 +289,110 @@ pub fn apply_route_result(pcb: &mut Pcb, r: &RouteResult) {
         pcb.add_debug_rt(*rt);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use memegeom::geom::math::eq;
+    use memegeom::primitive::shape::Shape;
+    use memegeom::primitive::{path, pt};
+
+    use super::*;
+    use crate::model::pcb::{LayerSet, LayerShape};
+    use crate::testing::test_pcb;
+
+    fn radius(wire: &Wire) -> f64 {
+        let Shape::Path(p) = &wire.shape.shape else { panic!("wire is not a path") };
+        p.r()
+    }
+
+    // Routes net A of the test board and adds an unrelated wire of net B.
+    fn routed_pcb() -> (Pcb, Id, Id) {
+        let mut pcb = test_pcb();
+        let (a, b) = (pcb.to_id("A"), pcb.to_id("B"));
+        let res = GridRouter::new(pcb.clone(), vec![a]).route().unwrap();
+        assert!(!res.failed);
+        apply_route_result(&mut pcb, &res);
+        let shape = path(&[pt(1.0, 1.0), pt(3.0, 1.0)], 0.1).shape();
+        pcb.add_wire(Wire { shape: LayerShape { layers: LayerSet::one(0), shape }, net_id: b });
+        (pcb, a, b)
+    }
+
+    #[test]
+    fn validate_routed_board() {
+        let pcb = test_pcb();
+        let order = vec![pcb.to_id("A"), pcb.to_id("B")];
+        let mut res = GridRouter::new(pcb.clone(), order).route().unwrap();
+        assert!(!res.failed);
+        assert!(res.validate(&pcb).is_empty());
+
+        // R2's pad has radius 0.5 at (16, 10), so this is too close to it.
+        let shape = path(&[pt(10.0, 9.3), pt(16.0, 9.3)], 0.1).shape();
+        let net_id = pcb.to_id("B");
+        res.wires.push(Wire { shape: LayerShape { layers: LayerSet::one(0), shape }, net_id });
+        let violations = res.validate(&pcb);
+        assert_eq!(violations.len(), 1, "{violations:?}");
+        assert_eq!(violations[0].net_id, net_id);
+    }
+
+    #[test]
+    fn stats_half_routed() {
+        let pcb = test_pcb();
+        let (a, b) = (pcb.to_id("A"), pcb.to_id("B"));
+        let mut res = GridRouter::new(pcb.clone(), vec![a]).route().unwrap();
+        assert!(!res.failed);
+        res.merge(RouteResult { failed: true, failed_nets: vec![b], ..Default::default() });
+        let stats = res.stats(&pcb);
+        assert_eq!(stats.total_nets, 2);
+        assert_eq!(stats.completed_nets, 1);
+        assert!(eq(stats.completion_ratio, 0.5));
+        assert_eq!(stats.vias, res.vias.len());
+        // R1 and R2's pads are just over 11 mm apart.
+        assert!(stats.total_length > 11.0, "{stats:?}");
+    }
+
+    #[test]
+    fn mutate_failed_earlier_moves_failed_net() {
+        let pcb = test_pcb();
+        let (a, b) = (pcb.to_id("A"), pcb.to_id("B"));
+        let router = Router::new(pcb);
+        let mut s = RouteState(vec![a, b]);
+        // Nothing failed yet.
+        router.mutate_failed_earlier(&mut s);
+        assert_eq!(s.0, [a, b]);
+        *router.best.lock().unwrap() = Some((0.5, vec![b]));
+        router.mutate_failed_earlier(&mut s);
+        assert_eq!(s.0, [b, a]);
+    }
+
+    #[test]
+    fn merge_dedupes_failed_nets() {
+        let mut res = RouteResult { failed: true, failed_nets: vec![1, 2], ..Default::default() };
+        res.merge(RouteResult { failed: true, failed_nets: vec![2, 3], ..Default::default() });
+        assert!(res.failed);
+        assert_eq!(res.failed_nets, [1, 2, 3]);
+    }
+
+    #[test]
+    fn reroute_net_with_radius_only_widens_that_net() {
+        let (mut pcb, a, b) = routed_pcb();
+        reroute_net_with_radius(&mut pcb, a, 0.3).unwrap();
+        assert!(pcb.net_wires(a).count() > 0);
+        assert!(pcb.net_wires(a).all(|v| eq(radius(v), 0.3)));
+        assert_eq!(pcb.net_wires(b).count(), 1);
+        assert!(pcb.net_wires(b).all(|v| eq(radius(v), 0.1)));
+    }
+
+    #[test]
+    fn reroute_net_with_radius_failure_keeps_routing() {
+        let (mut pcb, a, _) = routed_pcb();
+        let before: Vec<_> = pcb.wires().iter().map(|v| (v.net_id, radius(v))).collect();
+        // Far too wide to fit on the board.
+        assert!(reroute_net_with_radius(&mut pcb, a, 50.0).is_err());
+        let after: Vec<_> = pcb.wires().iter().map(|v| (v.net_id, radius(v))).collect();
+        assert_eq!(before.len(), after.len());
+        for ((n0, r0), (n1, r1)) in before.into_iter().zip(after) {
+            assert_eq!(n0, n1);
+            assert!(eq(r0, r1));
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use eyre::{eyre, Result};
+use memegeom::geom::math::{eq, pt_eq};
+use memegeom::primitive::point::Pt;
+use memegeom::primitive::pt;
+use memegeom::primitive::shape::Shape;
+
+use crate::model::pcb::{LayerId, Pcb};
+use crate::name::Id;
+use crate::route::arcs::corners;
+use crate::route::place_model::PlaceModel;
+
+const MITER_LIMIT: f64 = 4.0; // Maximum miter length at a corner, as a multiple of the offset.
+const STITCH_PITCH: f64 = 2.0; // Distance between stitching vias along a guard trace, in mm.
+
+// Adds guard traces for |shield_net| on either side of every wire in |net_id|,
+// |gap| away from the wire edge. The guard traces have width |width| and are
+// stitched with vias every STITCH_PITCH along their length. Parts of a guard trace
+// which would be blocked by other nets are left out, as are blocked vias.
+pub fn add_shield(
+    pcb: &mut Pcb,
+    net_id: Id,
+    shield_net: Id,
+    gap: f64,
+    width: f64,
+) -> Result<()> {
+    if pcb.via_padstacks().is_empty() {
+        return Err(eyre!("no via padstacks for shield"));
+    }
+    let r = width / 2.0;
+    let mut place = PlaceModel::new(pcb.clone());
+    let mut wires = Vec::new();
+    let mut vias = Vec::new();
+    for wire in pcb.net_wires(net_id) {
+        let Shape::Path(s) = &wire.shape.shape else { continue };
+        let Some(layer) = wire.shape.layers.id() else { continue };
+        let mut pts = corners(s.pts());
+        pts.dedup_by(|a, b| pt_eq(*a, *b));
+        if pts.len() < 2 {
+            continue;
+        }
+        let offset = s.r() + gap + r;
+        for side in [-1.0, 1.0] {
+            let guard = offset_polyline(&pts, side * offset);
+            for run in unblocked_runs(&place, shield_net, layer, &guard, r) {
+                let wire = place.create_wire_radius(shield_net, layer, &run, r);
+                place.add_wire(&wire);
+                wires.push(wire);
+                for p in stitch_pts(&run, STITCH_PITCH) {
+                    let via = place.create_via(shield_net, p);
+                    if !place.is_via_blocked_by_other_nets(&via) {
+                        place.add_via(&via);
+                        vias.push(via);
+                    }
+                }
+            }
+        }
+    }
+    for wire in wires {
+        pcb.add_wire(wire);
+    }
+    for via in vias {
+        pcb.add_via(via);
+    }
+    Ok(())
+}
+
+// Unit normal to the left of the segment |a|-|b|.
+fn normal(a: Pt, b: Pt) -> Pt {
+    let d = a.dist(b);
+    pt(-(b.y - a.y) / d, (b.x - a.x) / d)
+}
+
+// Offsets a polyline by |offset| to its left (right if negative), joining
+// segments with mitered corners. Consecutive points must be distinct.
+fn offset_polyline(pts: &[Pt], offset: f64) -> Vec<Pt> {
+    let normals: Vec<Pt> = pts.windows(2).map(|v| normal(v[0], v[1])).collect();
+    pts.iter()
+        .enumerate()
+        .map(|(i, &p)| {
+            let n0 = normals[i.saturating_sub(1)];
+            let n1 = normals[i.min(normals.len() - 1)];
+            let m = n0 + n1;
+            let len = m.dist(pt(0.0, 0.0));
+            // Segments which double back on themselves have no miter.
+            if eq(len, 0.0) {
+                return p + offset * n1;
+            }
+            let m = (1.0 / len) * m;
+            let cos = m.x * n1.x + m.y * n1.y;
+            let miter = (offset.abs() / cos).min(offset.abs() * MITER_LIMIT);
+            p + (offset.signum() * miter) * m
+        })
+        .collect()
+}
+
+// Splits a polyline into runs of segments which aren't blocked by other nets.
+fn unblocked_runs(
+    place: &PlaceModel,
+    net_id: Id,
+    layer: LayerId,
+    pts: &[Pt],
+    r: f64,
+) -> Vec<Vec<Pt>> {
+    let mut runs = Vec::new();
+    let mut run: Vec<Pt> = Vec::new();
+    for v in pts.windows(2) {
+        let seg = place.create_wire_radius(net_id, layer, v, r);
+        if place.is_wire_blocked(&seg) {
+            if !run.is_empty() {
+                runs.push(std::mem::take(&mut run));
+            }
+            continue;
+        }
+        if run.is_empty() {
+            run.push(v[0]);
+        }
+        run.push(v[1]);
+    }
+    if !run.is_empty() {
+        runs.push(run);
+    }
+    runs
+}
+
+// Returns points every |pitch| along a polyline, starting at its first point.
+// The last point is always included.
+fn stitch_pts(pts: &[Pt], pitch: f64) -> Vec<Pt> {
+    let mut out = vec![pts[0]];
+    let mut next = pitch; // Distance along the polyline of the next point.
+    let mut start = 0.0; // Distance along the polyline of the current segment.
+    for v in pts.windows(2) {
+        let len = v[0].dist(v[1]);
+        while next < start + len && !eq(next, start + len) {
+            let t = (next - start) / len;
+            out.push(v[0] + t * (v[1] - v[0]));
+            next += pitch;
+        }
+        start += len;
+    }
+    out.push(pts[pts.len() - 1]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use memegeom::primitive::{path, ShapeOps};
+
+    use super::*;
+    use crate::model::pcb::{LayerSet, LayerShape, Wire};
+    use crate::testing::test_pcb;
+
+    #[test]
+    fn shield_straight_wire() {
+        let mut pcb = test_pcb();
+        let (a, b) = (pcb.to_id("A"), pcb.to_id("B"));
+        let shape = path(&[pt(8.0, 5.0), pt(14.0, 5.0)], 0.1).shape();
+        pcb.add_wire(Wire { shape: LayerShape { layers: LayerSet::one(0), shape }, net_id: b });
+
+        add_shield(&mut pcb, b, a, 0.5, 0.2).unwrap();
+        // Guard centers are the wire radius, gap and guard radius away.
+        let mut ys: Vec<f64> = pcb
+            .net_wires(a)
+            .map(|w| {
+                let Shape::Path(p) = &w.shape.shape else { panic!("guard is not a path") };
+                assert!(eq(p.r(), 0.1));
+                assert_eq!(p.pts().len(), 2, "{p:?}");
+                assert!(eq(p.pts()[0].y, p.pts()[1].y), "{p:?}");
+                p.pts()[0].y
+            })
+            .collect();
+        ys.sort_by(f64::total_cmp);
+        assert_eq!(ys.len(), 2, "{ys:?}");
+        assert!(eq(ys[0], 4.3) && eq(ys[1], 5.7), "{ys:?}");
+        // Vias every 2 mm along both 6 mm guards.
+        assert_eq!(pcb.vias().iter().filter(|v| v.net_id == a).count(), 8);
+    }
+}
@@ -0,0 +1,58 @@
+use crate::dsn::load_dsn;
+use crate::model::pcb::Pcb;
+
+// A small two layer board for tests. R1 to R4 each have a single round pad.
+// R1 and R2 are joined by net A, which is in the "wide" class, and R3 and R4
+// by net B, which is in the default class. Units are um, so R1's pad is at
+// (5, 11) mm after rotation, R2's is at (16, 10) mm, R3's at (6, 5) mm and
+// R4's at (16, 5) mm.
+pub(crate) const TEST_DSN: &str = r#"
+(pcb test
+  (parser
+    (string_quote ")
+    (space_in_quoted_tokens on)
+  )
+  (resolution um 10)
+  (unit um)
+  (structure
+    (layer F.Cu (type signal))
+    (layer B.Cu (type signal))
+    (boundary (rect pcb 0 0 20000 20000))
+    (via via600)
+    (rule (width 200) (clearance 200))
+  )
+  (placement
+    (component pad_image
+      (place R1 5000 10000 front 90)
+      (place R2 15000 10000 front 0)
+      (place R3 5000 5000 front 0)
+      (place R4 15000 5000 front 0)
+    )
+  )
+  (library
+    (image pad_image
+      (pin round 1 1000 0)
+    )
+    (padstack round
+      (shape (circle F.Cu 1000))
+      (attach off)
+    )
+    (padstack via600
+      (shape (circle F.Cu 600))
+      (shape (circle B.Cu 600))
+      (attach off)
+    )
+  )
+  (network
+    (net A (pins R1-1 R2-1))
+    (net B (pins R3-1 R4-1))
+    (class default (rule (width 200) (clearance 200)))
+    (class wide A (circuit (use_via via600)) (rule (width 400)))
+  )
+  (wiring)
+)
+"#;
+
+pub(crate) fn test_pcb() -> Pcb {
+    load_dsn(TEST_DSN).unwrap()
+}